@@ -0,0 +1,562 @@
+// AES3 channel status data, as stored in the AESD chunk. 24 bytes, bits
+// numbered in transmission order, so bit 0 is the least significant bit of
+// each byte. the standard writes multi bit states in that order too ("01"
+// means bit 6 clear, bit 7 set), and the state values below follow the
+// standard's notation, first transmitted bit most significant.
+//
+// professional layout (AES3 / IEC 60958-4):
+//   byte 0      professional, non-audio, emphasis, lock, sample rate
+//   byte 1      channel mode, user bits management
+//   byte 2      auxiliary bits use, word length, alignment level
+//   byte 3      multichannel mode
+//   byte 4      reference signal, sample rate extensions
+//   bytes 6-9   source identifier, 4 ASCII chars
+//   bytes 10-13 destination identifier, 4 ASCII chars
+//   bytes 14-17 local sample address code, 32 bit little endian
+//   bytes 18-21 time of day sample address code, 32 bit little endian
+//   byte 22     reliability flags
+//   byte 23     CRC-8 over bytes 0-22
+//
+// consumer blocks (IEC 60958-3) only carry emphasis, sample rate and word
+// length at different positions, everything else is left at its default
+use super::chunks::ChunkError;
+
+pub const CHANNEL_STATUS_SIZE: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Emphasis {
+    #[default]
+    NotIndicated,
+    None,
+    CD50_15, // 50/15 µs
+    CCITTJ17,
+    Reserved(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleRate {
+    #[default]
+    NotIndicated,
+    Hz32000,
+    Hz44100,
+    Hz48000,
+}
+
+impl SampleRate {
+    pub fn hz(self) -> Option<u32> {
+        match self {
+            SampleRate::NotIndicated => None,
+            SampleRate::Hz32000 => Some(32000),
+            SampleRate::Hz44100 => Some(44100),
+            SampleRate::Hz48000 => Some(48000),
+        }
+    }
+
+    pub fn from_hz(hz: u32) -> SampleRate {
+        match hz {
+            32000 => SampleRate::Hz32000,
+            44100 => SampleRate::Hz44100,
+            48000 => SampleRate::Hz48000,
+            _ => SampleRate::NotIndicated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    #[default]
+    NotIndicated,
+    TwoChannel,
+    SingleChannel,
+    PrimarySecondary,
+    Stereo,
+    UserDefined,
+    SingleChannelDoubleRate,
+    StereoLeftDoubleRate,
+    StereoRightDoubleRate,
+    Multichannel, // see `multichannel_mode`
+    Reserved(u8),
+}
+
+impl ChannelMode {
+    fn from_state(state: u8) -> ChannelMode {
+        match state {
+            0b0000 => ChannelMode::NotIndicated,
+            0b0001 => ChannelMode::TwoChannel,
+            0b0010 => ChannelMode::SingleChannel,
+            0b0011 => ChannelMode::PrimarySecondary,
+            0b0100 => ChannelMode::Stereo,
+            0b0111 => ChannelMode::UserDefined,
+            0b1000 => ChannelMode::SingleChannelDoubleRate,
+            0b1001 => ChannelMode::StereoLeftDoubleRate,
+            0b1010 => ChannelMode::StereoRightDoubleRate,
+            0b1111 => ChannelMode::Multichannel,
+            s => ChannelMode::Reserved(s),
+        }
+    }
+
+    fn to_state(self) -> u8 {
+        match self {
+            ChannelMode::NotIndicated => 0b0000,
+            ChannelMode::TwoChannel => 0b0001,
+            ChannelMode::SingleChannel => 0b0010,
+            ChannelMode::PrimarySecondary => 0b0011,
+            ChannelMode::Stereo => 0b0100,
+            ChannelMode::UserDefined => 0b0111,
+            ChannelMode::SingleChannelDoubleRate => 0b1000,
+            ChannelMode::StereoLeftDoubleRate => 0b1001,
+            ChannelMode::StereoRightDoubleRate => 0b1010,
+            ChannelMode::Multichannel => 0b1111,
+            ChannelMode::Reserved(s) => s & 0x0F,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AESChannelStatus {
+    pub professional: bool,
+    pub non_audio: bool,
+    pub emphasis: Emphasis,
+    pub locked: bool,
+    pub sample_rate: SampleRate,
+    pub channel_mode: ChannelMode,
+    pub user_bits_management: u8, // raw state, byte 1 bits 4-7
+    pub max_word_length: u8,      // 20 or 24 bits
+    pub word_length: Option<u8>,  // None when not indicated
+    pub alignment_level: u8,      // raw state, byte 2 bits 6-7
+    pub multichannel_mode: u8,    // raw byte 3
+    pub reference_signal: u8,     // raw byte 4
+    pub source: [u8; 4],
+    pub destination: [u8; 4],
+    pub local_sample_address: u32,
+    pub time_of_day_sample_address: u32,
+    pub reliability_flags: u8,
+    pub crc: u8,
+}
+
+impl Default for AESChannelStatus {
+    fn default() -> Self {
+        AESChannelStatus {
+            professional: true,
+            non_audio: false,
+            emphasis: Emphasis::default(),
+            locked: true,
+            sample_rate: SampleRate::default(),
+            channel_mode: ChannelMode::default(),
+            user_bits_management: 0,
+            max_word_length: 20,
+            word_length: None,
+            alignment_level: 0,
+            multichannel_mode: 0,
+            reference_signal: 0,
+            source: [0; 4],
+            destination: [0; 4],
+            local_sample_address: 0,
+            time_of_day_sample_address: 0,
+            reliability_flags: 0,
+            crc: 0,
+        }
+    }
+}
+
+// reads `len` bits from `first_bit` on, in the standard's notation
+fn get_state(byte: u8, first_bit: u8, len: u8) -> u8 {
+    (first_bit..first_bit + len)
+        .fold(0, |state, bit| (state << 1) | ((byte >> bit) & 1))
+}
+
+fn set_state(byte: &mut u8, first_bit: u8, len: u8, state: u8) {
+    for (idx, bit) in (first_bit..first_bit + len).enumerate() {
+        let value = (state >> (len as usize - 1 - idx)) & 1;
+        *byte = (*byte & !(1 << bit)) | (value << bit);
+    }
+}
+
+// word length states and how many bits short of the maximum length each
+// is. the two layouts use different codes: AES3 byte 2 bits 3 - 5 for
+// professional blocks, IEC 60958-3 bits 33 - 35 for consumer ones
+const PROFESSIONAL_WORD_LENGTHS: [(u8, u8); 5] =
+    [(0b101, 0), (0b001, 1), (0b010, 2), (0b100, 3), (0b110, 4)];
+const CONSUMER_WORD_LENGTHS: [(u8, u8); 5] =
+    [(0b001, 0), (0b110, 1), (0b010, 2), (0b101, 3), (0b100, 4)];
+
+fn word_length_table(professional: bool) -> &'static [(u8, u8)] {
+    if professional {
+        &PROFESSIONAL_WORD_LENGTHS
+    } else {
+        &CONSUMER_WORD_LENGTHS
+    }
+}
+
+fn word_length_from_state(
+    professional: bool,
+    state: u8,
+    max: u8,
+) -> Option<u8> {
+    word_length_table(professional)
+        .iter()
+        .find(|(s, _)| *s == state)
+        .map(|(_, short)| max - short)
+}
+
+// 000, not indicated, for lengths the table has no state for
+fn word_length_to_state(professional: bool, length: Option<u8>, max: u8) -> u8 {
+    let short = match length.and_then(|l| max.checked_sub(l)) {
+        Some(short) => short,
+        None => return 0b000,
+    };
+    word_length_table(professional)
+        .iter()
+        .find(|(_, s)| *s == short)
+        .map_or(0b000, |(state, _)| *state)
+}
+
+// CRC-8 with polynomial x^8 + x^4 + x^3 + x^2 + 1 and an all ones preset,
+// bits fed least significant first, hence the reflected polynomial
+pub fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0xFF, |crc, b| {
+        (0..8).fold(crc ^ b, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xB8
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl AESChannelStatus {
+    pub fn decode(data: &[u8; CHANNEL_STATUS_SIZE]) -> AESChannelStatus {
+        let mut status = AESChannelStatus {
+            professional: data[0] & 1 == 1,
+            non_audio: (data[0] >> 1) & 1 == 1,
+            ..AESChannelStatus::default()
+        };
+
+        if !status.professional {
+            status.emphasis = match get_state(data[0], 3, 3) {
+                0b000 => Emphasis::None,
+                0b100 => Emphasis::CD50_15,
+                s => Emphasis::Reserved(s),
+            };
+            status.sample_rate = match get_state(data[3], 0, 4) {
+                0b0000 => SampleRate::Hz44100,
+                0b0100 => SampleRate::Hz48000,
+                0b1100 => SampleRate::Hz32000,
+                _ => SampleRate::NotIndicated,
+            };
+            status.max_word_length = if data[4] & 1 == 1 { 24 } else { 20 };
+            status.word_length = word_length_from_state(
+                false,
+                get_state(data[4], 1, 3),
+                status.max_word_length,
+            );
+            return status;
+        }
+
+        status.emphasis = match get_state(data[0], 2, 3) {
+            0b000 => Emphasis::NotIndicated,
+            0b100 => Emphasis::None,
+            0b110 => Emphasis::CD50_15,
+            0b111 => Emphasis::CCITTJ17,
+            s => Emphasis::Reserved(s),
+        };
+        status.locked = (data[0] >> 5) & 1 == 0;
+        status.sample_rate = match get_state(data[0], 6, 2) {
+            0b01 => SampleRate::Hz48000,
+            0b10 => SampleRate::Hz44100,
+            0b11 => SampleRate::Hz32000,
+            _ => SampleRate::NotIndicated,
+        };
+
+        status.channel_mode = ChannelMode::from_state(get_state(data[1], 0, 4));
+        status.user_bits_management = get_state(data[1], 4, 4);
+
+        // auxiliary bits state 001 means they carry audio, 24 bit samples
+        status.max_word_length = if get_state(data[2], 0, 3) == 0b001 {
+            24
+        } else {
+            20
+        };
+        status.word_length = word_length_from_state(
+            true,
+            get_state(data[2], 3, 3),
+            status.max_word_length,
+        );
+        status.alignment_level = get_state(data[2], 6, 2);
+
+        status.multichannel_mode = data[3];
+        status.reference_signal = data[4];
+        status.source.copy_from_slice(&data[6..10]);
+        status.destination.copy_from_slice(&data[10..14]);
+        status.local_sample_address = le_u32(&data[14..18]);
+        status.time_of_day_sample_address = le_u32(&data[18..22]);
+        status.reliability_flags = data[22];
+        status.crc = data[23];
+
+        status
+    }
+
+    // the CRC byte is always recomputed for professional blocks, so `crc`
+    // doesn't need to be kept up to date by hand
+    pub fn encode(&self) -> [u8; CHANNEL_STATUS_SIZE] {
+        let mut data = [0u8; CHANNEL_STATUS_SIZE];
+        data[0] = self.professional as u8 | (self.non_audio as u8) << 1;
+
+        if !self.professional {
+            let emphasis = match self.emphasis {
+                Emphasis::CD50_15 => 0b100,
+                Emphasis::Reserved(s) => s,
+                _ => 0b000,
+            };
+            set_state(&mut data[0], 3, 3, emphasis);
+            let rate = match self.sample_rate {
+                SampleRate::Hz44100 => 0b0000,
+                SampleRate::Hz48000 => 0b0100,
+                SampleRate::Hz32000 => 0b1100,
+                SampleRate::NotIndicated => 0b1000,
+            };
+            set_state(&mut data[3], 0, 4, rate);
+            data[4] = (self.max_word_length == 24) as u8;
+            set_state(
+                &mut data[4],
+                1,
+                3,
+                word_length_to_state(
+                    false,
+                    self.word_length,
+                    self.max_word_length,
+                ),
+            );
+            return data;
+        }
+
+        let emphasis = match self.emphasis {
+            Emphasis::NotIndicated => 0b000,
+            Emphasis::None => 0b100,
+            Emphasis::CD50_15 => 0b110,
+            Emphasis::CCITTJ17 => 0b111,
+            Emphasis::Reserved(s) => s,
+        };
+        set_state(&mut data[0], 2, 3, emphasis);
+        set_state(&mut data[0], 5, 1, !self.locked as u8);
+        let rate = match self.sample_rate {
+            SampleRate::NotIndicated => 0b00,
+            SampleRate::Hz48000 => 0b01,
+            SampleRate::Hz44100 => 0b10,
+            SampleRate::Hz32000 => 0b11,
+        };
+        set_state(&mut data[0], 6, 2, rate);
+
+        set_state(&mut data[1], 0, 4, self.channel_mode.to_state());
+        set_state(&mut data[1], 4, 4, self.user_bits_management);
+
+        let aux = if self.max_word_length == 24 {
+            0b001
+        } else {
+            0b000
+        };
+        set_state(&mut data[2], 0, 3, aux);
+        set_state(
+            &mut data[2],
+            3,
+            3,
+            word_length_to_state(true, self.word_length, self.max_word_length),
+        );
+        set_state(&mut data[2], 6, 2, self.alignment_level);
+
+        data[3] = self.multichannel_mode;
+        data[4] = self.reference_signal;
+        data[6..10].copy_from_slice(&self.source);
+        data[10..14].copy_from_slice(&self.destination);
+        data[14..18].copy_from_slice(&self.local_sample_address.to_le_bytes());
+        data[18..22]
+            .copy_from_slice(&self.time_of_day_sample_address.to_le_bytes());
+        data[22] = self.reliability_flags;
+        data[23] = crc8(&data[..23]);
+
+        data
+    }
+
+    // consumer blocks have no CRC, so they always pass
+    pub fn validate_crc(
+        data: &[u8; CHANNEL_STATUS_SIZE],
+    ) -> Result<(), ChunkError> {
+        if data[0] & 1 == 0 {
+            return Ok(());
+        }
+
+        let expected = crc8(&data[..23]);
+        if data[23] != expected {
+            return Err(ChunkError::InvalidCRC(expected, data[23]));
+        }
+
+        Ok(())
+    }
+
+    pub fn source_str(&self) -> String {
+        identifier_str(&self.source)
+    }
+
+    pub fn destination_str(&self) -> String {
+        identifier_str(&self.destination)
+    }
+
+    // identifiers are 7 bit ASCII, padded with NULs
+    pub fn set_source(&mut self, id: &str) {
+        self.source = identifier_bytes(id);
+    }
+
+    pub fn set_destination(&mut self, id: &str) {
+        self.destination = identifier_bytes(id);
+    }
+}
+
+fn identifier_str(bytes: &[u8; 4]) -> String {
+    bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| (b & 0x7F) as char)
+        .collect()
+}
+
+fn identifier_bytes(id: &str) -> [u8; 4] {
+    let mut bytes = [0u8; 4];
+    for (b, c) in bytes.iter_mut().zip(id.chars()) {
+        *b = if c.is_ascii() { c as u8 } else { b'?' };
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // bytes 0 - 22 of a professional block, CRC worked out bit by bit with
+    // the shift register described in AES3
+    const MINIMAL: [u8; 23] = [
+        0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    const STEREO_48K: [u8; 23] = [
+        0x85, 0x02, 0x2C, 0, 0, 0, b'A', b'B', b'C', b'D', 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    fn crc_reference_blocks() {
+        // the check value published for the AES3 CRC, catalogued as
+        // CRC-8/AES and CRC-8/TECH-3250 after EBU Tech 3250
+        assert_eq!(crc8(b"123456789"), 0x97);
+        assert_eq!(crc8(&MINIMAL), 0x32);
+        assert_eq!(crc8(&STEREO_48K), 0xF9);
+    }
+
+    #[test]
+    fn encodes_professional_block() {
+        let mut status = AESChannelStatus {
+            emphasis: Emphasis::None,
+            sample_rate: SampleRate::Hz48000,
+            channel_mode: ChannelMode::Stereo,
+            max_word_length: 24,
+            word_length: Some(24),
+            ..AESChannelStatus::default()
+        };
+        status.set_source("ABCD");
+
+        let data = status.encode();
+        assert_eq!(data[..23], STEREO_48K);
+        assert_eq!(data[23], 0xF9);
+        assert!(AESChannelStatus::validate_crc(&data).is_ok());
+
+        status.crc = 0xF9;
+        assert_eq!(AESChannelStatus::decode(&data), status);
+        assert_eq!(AESChannelStatus::default().encode()[..23], MINIMAL);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut data = AESChannelStatus::default().encode();
+        data[10] = b'X';
+        assert!(matches!(
+            AESChannelStatus::validate_crc(&data),
+            Err(ChunkError::InvalidCRC(_, 0x32))
+        ));
+
+        // consumer blocks carry no CRC
+        data[0] = 0;
+        assert!(AESChannelStatus::validate_crc(&data).is_ok());
+    }
+
+    // word lengths as coded in AES3 byte 2, with the auxiliary bits state
+    // in bits 0 - 2 giving the maximum
+    #[test]
+    fn professional_word_lengths() {
+        let vectors = [
+            (0x24, 24, Some(23)), // aux 001, word length 001
+            (0x2C, 24, Some(24)), // 101
+            (0x14, 24, Some(22)), // 010
+            (0x0C, 24, Some(21)), // 100
+            (0x1C, 24, Some(20)), // 110
+            (0x20, 20, Some(19)),
+            (0x18, 20, Some(16)),
+            (0x04, 24, None),
+        ];
+        for (byte, max, length) in vectors {
+            let mut data = AESChannelStatus::default().encode();
+            data[2] = byte;
+            let status = AESChannelStatus::decode(&data);
+            assert_eq!(status.max_word_length, max, "{:#04x}", byte);
+            assert_eq!(status.word_length, length, "{:#04x}", byte);
+            assert_eq!(status.encode()[2], byte);
+        }
+    }
+
+    // word lengths as coded in IEC 60958-3 byte 4, bit 32 giving the
+    // maximum. bit 32 is the least significant bit of the byte
+    #[test]
+    fn consumer_word_lengths() {
+        let vectors = [
+            (0x09, 24, Some(24)), // bits 32 and 35
+            (0x03, 24, Some(20)), // 32, 33
+            (0x05, 24, Some(22)), // 32, 34
+            (0x07, 24, Some(23)), // 32, 33, 34
+            (0x0B, 24, Some(21)), // 32, 33, 35
+            (0x08, 20, Some(20)), // 35
+            (0x02, 20, Some(16)), // 33
+            (0x0A, 20, Some(17)), // 33, 35
+            (0x01, 24, None),
+        ];
+        for (byte, max, length) in vectors {
+            let mut data = [0; CHANNEL_STATUS_SIZE];
+            data[4] = byte;
+            let status = AESChannelStatus::decode(&data);
+            assert!(!status.professional);
+            assert_eq!(status.max_word_length, max, "{:#04x}", byte);
+            assert_eq!(status.word_length, length, "{:#04x}", byte);
+            assert_eq!(status.encode()[4], byte);
+        }
+
+        // bits 32, 34 and 35, a reserved state
+        let mut data = [0; CHANNEL_STATUS_SIZE];
+        data[4] = 0x0D;
+        assert_eq!(AESChannelStatus::decode(&data).word_length, None);
+    }
+
+    #[test]
+    fn word_length_round_trips() {
+        for (max, length) in [(24, Some(20)), (24, Some(23)), (20, Some(16))] {
+            let status = AESChannelStatus {
+                max_word_length: max,
+                word_length: length,
+                ..AESChannelStatus::default()
+            };
+            let decoded = AESChannelStatus::decode(&status.encode());
+            assert_eq!(decoded.max_word_length, max);
+            assert_eq!(decoded.word_length, length);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// APPL chunks hold data for a particular application, identified by a four
+// character signature. the registry maps signatures to decoders so callers
+// get typed values back instead of raw bytes, and since every decoded value
+// can encode itself again, the chunks round trip through the writer.
+//
+// the spec defines one layout itself: 'pdos' (Apple II) and 'stoc' (any
+// other platform) chunks start with a pascal string naming the application,
+// the rest of the data belongs to that application
+use super::{
+    chunks::{ApplicationSpecificChunk, ChunkError},
+    ids::ChunkID,
+    macroman::{self, TextDecoding},
+    writer,
+};
+use std::{any::Any, collections::HashMap, fmt::Debug};
+
+pub const PDOS: &ChunkID = b"pdos";
+pub const STOC: &ChunkID = b"stoc";
+
+pub trait ApplicationData: Debug {
+    fn signature(&self) -> ChunkID;
+
+    // everything after the signature
+    fn encode(&self) -> Vec<u8>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    // whether storing this value should replace an existing chunk. by
+    // default any chunk with the same signature
+    fn replaces(&self, chunk: &ApplicationSpecificChunk) -> bool {
+        chunk.application_signature == self.signature()
+    }
+}
+
+impl dyn ApplicationData {
+    pub fn downcast_ref<T: ApplicationData + 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref::<T>()
+    }
+}
+
+pub type ApplicationDecoder =
+    fn(ChunkID, &[u8]) -> Result<Box<dyn ApplicationData>, ChunkError>;
+
+// the 'pdos' / 'stoc' layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedApplicationData {
+    pub signature: ChunkID,
+    pub application_name: String,
+    pub data: Vec<u8>,
+}
+
+impl NamedApplicationData {
+    pub fn decode(
+        signature: ChunkID,
+        bytes: &[u8],
+    ) -> Result<NamedApplicationData, ChunkError> {
+        let len = *bytes
+            .first()
+            .ok_or(ChunkError::InvalidData("missing application name"))?
+            as usize;
+        let name = bytes
+            .get(1..1 + len)
+            .ok_or(ChunkError::InvalidData("truncated application name"))?;
+        // count byte plus text is padded to an even length
+        let data_start = (1 + len + 1) & !1;
+
+        Ok(NamedApplicationData {
+            signature,
+            application_name: macroman::decode(name, TextDecoding::Lossy)?,
+            data: bytes.get(data_start..).unwrap_or(&[]).to_vec(),
+        })
+    }
+}
+
+impl ApplicationData for NamedApplicationData {
+    fn signature(&self) -> ChunkID {
+        self.signature
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        // writing to a vec can't fail
+        writer::write_pstring(&mut bytes, &self.application_name).unwrap();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    // 'stoc' is shared by every application, only replace our own chunk
+    fn replaces(&self, chunk: &ApplicationSpecificChunk) -> bool {
+        chunk.application_signature == self.signature
+            && NamedApplicationData::decode(
+                chunk.application_signature,
+                &chunk.data,
+            )
+            .is_ok_and(|d| d.application_name == self.application_name)
+    }
+}
+
+fn decode_named(
+    signature: ChunkID,
+    bytes: &[u8],
+) -> Result<Box<dyn ApplicationData>, ChunkError> {
+    Ok(Box::new(NamedApplicationData::decode(signature, bytes)?))
+}
+
+#[derive(Debug, Clone)]
+pub struct ApplicationRegistry {
+    decoders: HashMap<ChunkID, ApplicationDecoder>,
+}
+
+impl ApplicationRegistry {
+    // with the built in 'pdos' and 'stoc' decoders
+    pub fn new() -> ApplicationRegistry {
+        let mut registry = ApplicationRegistry::empty();
+        registry.register(*PDOS, decode_named);
+        registry.register(*STOC, decode_named);
+        registry
+    }
+
+    pub fn empty() -> ApplicationRegistry {
+        ApplicationRegistry {
+            decoders: HashMap::new(),
+        }
+    }
+
+    // replaces any decoder already registered for the signature, including
+    // the built in ones
+    pub fn register(
+        &mut self,
+        signature: ChunkID,
+        decoder: ApplicationDecoder,
+    ) {
+        self.decoders.insert(signature, decoder);
+    }
+
+    pub fn unregister(&mut self, signature: &ChunkID) {
+        self.decoders.remove(signature);
+    }
+
+    pub fn is_registered(&self, signature: &ChunkID) -> bool {
+        self.decoders.contains_key(signature)
+    }
+
+    // None when no decoder is registered for the chunk's signature
+    pub fn decode(
+        &self,
+        chunk: &ApplicationSpecificChunk,
+    ) -> Option<Result<Box<dyn ApplicationData>, ChunkError>> {
+        self.decoders
+            .get(&chunk.application_signature)
+            .map(|decode| decode(chunk.application_signature, &chunk.data))
+    }
+}
+
+impl Default for ApplicationRegistry {
+    fn default() -> Self {
+        ApplicationRegistry::new()
+    }
+}
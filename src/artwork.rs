@@ -0,0 +1,82 @@
+// embedded artwork lives in APIC frames of the ID3 chunk. these helpers work
+// on an `id3::Tag` directly so they can back both the reader and anything
+// that writes the tag back out
+pub use id3::frame::{Picture, PictureType};
+use id3::Tag;
+
+const APIC: &str = "APIC";
+
+pub fn pictures(tag: &Tag) -> Vec<&Picture> {
+    tag.pictures().collect()
+}
+
+pub fn picture_by_type(
+    tag: &Tag,
+    picture_type: PictureType,
+) -> Option<&Picture> {
+    tag.pictures().find(|p| p.picture_type == picture_type)
+}
+
+// the front cover if there is one, otherwise the first picture in the tag
+pub fn front_cover(tag: &Tag) -> Option<&Picture> {
+    picture_by_type(tag, PictureType::CoverFront)
+        .or_else(|| tag.pictures().next())
+}
+
+// replaces any existing picture of the same type
+pub fn set_picture(tag: &mut Tag, picture: Picture) {
+    tag.remove_picture_by_type(picture.picture_type);
+    tag.add_picture(picture);
+}
+
+pub fn remove_picture(tag: &mut Tag, picture_type: PictureType) {
+    tag.remove_picture_by_type(picture_type);
+}
+
+pub fn clear_pictures(tag: &mut Tag) {
+    tag.remove(APIC);
+}
+
+// builds a picture, detecting the MIME type from the image data. returns
+// None when the format isn't recognised
+pub fn picture_from_image(
+    picture_type: PictureType,
+    description: &str,
+    data: Vec<u8>,
+) -> Option<Picture> {
+    let mime_type = sniff_mime_type(&data)?;
+
+    Some(Picture {
+        mime_type: mime_type.to_owned(),
+        picture_type,
+        description: description.to_owned(),
+        data,
+    })
+}
+
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            Some("image/webp")
+        }
+        _ => None,
+    }
+}
+
+// file extension for a picture's MIME type, handy when extracting artwork to
+// disk. ID3v2.2 stores a 3 character format instead of a MIME type, so those
+// are accepted as well
+pub fn file_extension(mime_type: &str) -> Option<&'static str> {
+    match mime_type.to_ascii_lowercase().as_str() {
+        "image/jpeg" | "image/jpg" | "jpg" => Some("jpg"),
+        "image/png" | "png" => Some("png"),
+        "image/gif" | "gif" => Some("gif"),
+        "image/bmp" | "bmp" => Some("bmp"),
+        "image/webp" => Some("webp"),
+        _ => None,
+    }
+}
@@ -0,0 +1,289 @@
+// async counterpart of AiffReader over tokio's io traits, behind the `tokio`
+// feature. chunk bodies are read into memory and go through the same
+// decoding as the stream reader, so the only async parts are the reads and
+// seeks themselves.
+//
+//     let mut reader = AsyncAiffReader::new(file);
+//     reader.parse().await?;
+//     let mut blocks = reader.sample_blocks::<i16>(4096).await?;
+//     while let Some(block) = blocks.next().await { ... }
+use super::{
+    chunks::{ChunkError, FormChunk},
+    detect::{self, FormKind},
+    ids,
+    options::ReaderOptions,
+    probe::{self, Compression},
+    samples::SampleType,
+    stream,
+    walk::{self, ChunkWalk},
+};
+use futures_core::Stream;
+use std::{
+    io::{self, SeekFrom},
+    marker::PhantomData,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
+
+pub struct AsyncAiffReader<R> {
+    inner: R,
+    kind: Option<FormKind>,
+    form: Option<FormChunk>,
+    id3v2_tag: Option<id3::Tag>,
+    compression: Option<Compression>,
+    sound: Option<(u64, u64)>, // start of the first frame, length
+    options: ReaderOptions,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncAiffReader<R> {
+    pub fn new(inner: R) -> AsyncAiffReader<R> {
+        AsyncAiffReader::with_options(inner, ReaderOptions::new())
+    }
+
+    // indexed chunks are skipped, there's no read_chunk to load them later
+    pub fn with_options(
+        inner: R,
+        options: ReaderOptions,
+    ) -> AsyncAiffReader<R> {
+        AsyncAiffReader {
+            inner,
+            kind: None,
+            form: None,
+            id3v2_tag: None,
+            compression: None,
+            sound: None,
+            options,
+        }
+    }
+
+    // reads every chunk except the sound data, which is only located
+    pub async fn parse(&mut self) -> Result<(), ChunkError> {
+        self.inner.seek(SeekFrom::Start(0)).await?;
+
+        let mut header = [0; detect::HEADER_SIZE];
+        self.inner.read_exact(&mut header).await?;
+        let (form_id, form_size) = walk::decode_header(&header);
+        let kind =
+            detect::detect(&header).ok_or(ChunkError::InvalidID(form_id))?;
+
+        let mut form = FormChunk::new();
+        form.set_form_type(*kind.form_type());
+        let form_end = walk::form_end(form_size, u64::MAX);
+        let mut chunks = ChunkWalk::new(form_end);
+
+        while let Some(pos) = chunks.next_header() {
+            let (id, size) =
+                match walk::read_header_async(&mut self.inner).await? {
+                    Some(h) => h,
+                    None => break,
+                };
+            chunks.advance(id, size);
+
+            if &id == ids::SOUND && self.sound.is_none() {
+                let mut offsets = [0; 8];
+                self.inner.read_exact(&mut offsets).await?;
+                let offset = u32::from_be_bytes([
+                    offsets[0], offsets[1], offsets[2], offsets[3],
+                ]);
+                let len = walk::sound_data_len(size, offset);
+                self.sound = Some((pos + 16 + offset as u64, len));
+            } else if stream::is_known(&id) && self.options.loads(&id) {
+                self.options.check_size(&id, size as u64)?;
+                let mut body = vec![];
+                (&mut self.inner)
+                    .take(size as u64)
+                    .read_to_end(&mut body)
+                    .await?;
+                if body.len() < size as usize {
+                    return Err(
+                        io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                    );
+                }
+
+                if &id == ids::COMMON {
+                    self.compression = probe::decode_compression(&body);
+                }
+                stream::add_chunk_body(
+                    &mut form,
+                    &mut self.id3v2_tag,
+                    &id,
+                    &body,
+                    &self.options,
+                )?;
+            }
+
+            // nothing follows sound data without a size, it runs to the end
+            if chunks.next_header().is_some() {
+                self.inner.seek(SeekFrom::Start(chunks.pos())).await?;
+            }
+        }
+
+        // ID3 tags are often appended after the FORM instead of put in a
+        // chunk, the sync reader finds those there too
+        if form_end != u64::MAX && self.options.loads(ids::ID3_CHUNK) {
+            self.read_bare_id3(&mut form, form_end).await?;
+        }
+
+        if form.common().is_none() {
+            return Err(ChunkError::InvalidData("missing COMM chunk"));
+        }
+        self.kind = Some(kind);
+        self.form = Some(form);
+
+        Ok(())
+    }
+
+    // a bare ID3v2 tag at `offset`, if there is one. the tag header has
+    // its own size
+    async fn read_bare_id3(
+        &mut self,
+        form: &mut FormChunk,
+        offset: u64,
+    ) -> Result<(), ChunkError> {
+        self.inner.seek(SeekFrom::Start(offset)).await?;
+        let mut header = vec![];
+        (&mut self.inner).take(10).read_to_end(&mut header).await?;
+        let tag_size = match detect::id3_tag_size(&header) {
+            Some(size) if header.starts_with(ids::ID3) => size,
+            _ => return Ok(()),
+        };
+        self.options.check_size(ids::ID3_CHUNK, tag_size as u64)?;
+
+        self.inner.seek(SeekFrom::Start(offset)).await?;
+        let mut tag = vec![];
+        (&mut self.inner)
+            .take(tag_size as u64)
+            .read_to_end(&mut tag)
+            .await?;
+        stream::add_chunk_body(
+            form,
+            &mut self.id3v2_tag,
+            ids::ID3_CHUNK,
+            &tag,
+            &self.options,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn kind(&self) -> Option<FormKind> {
+        self.kind
+    }
+
+    pub fn form(&self) -> &Option<FormChunk> {
+        &self.form
+    }
+
+    pub fn form_mut(&mut self) -> &mut Option<FormChunk> {
+        &mut self.form
+    }
+
+    pub fn id3v2_tag(&self) -> Option<&id3::Tag> {
+        self.id3v2_tag.as_ref()
+    }
+
+    pub fn compression(&self) -> Option<&Compression> {
+        self.compression.as_ref()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // streams the sound data as blocks of up to `frames_per_block` frames
+    // of interleaved sample points. `parse` has to have run first
+    pub async fn sample_blocks<T: SampleType>(
+        &mut self,
+        frames_per_block: usize,
+    ) -> Result<SampleBlocks<'_, R, T>, ChunkError> {
+        let common = self
+            .form
+            .as_ref()
+            .and_then(|f| f.common().as_ref())
+            .ok_or(ChunkError::InvalidData("form not parsed"))?;
+        if self
+            .compression
+            .as_ref()
+            .is_some_and(|c| !stream::is_uncompressed(c))
+        {
+            return Err(ChunkError::InvalidData("compressed sound data"));
+        }
+
+        let bit_rate = common.bit_rate;
+        let frame_size = walk::frame_size(common).max(1);
+        let frames = common.num_sample_frames as u64;
+
+        let (start, len) = self.sound.unwrap_or((0, 0));
+        // limited to the frame count in COMM, like the sync reader. in
+        // recovery mode a count of 0 means it was never written
+        let remaining = match frames {
+            0 if self.options.recovers() => len,
+            frames => len.min(frames * frame_size as u64),
+        };
+        self.inner.seek(SeekFrom::Start(start)).await?;
+
+        Ok(SampleBlocks {
+            inner: &mut self.inner,
+            buf: vec![0; frames_per_block.max(1) * frame_size],
+            filled: 0,
+            remaining,
+            frame_size,
+            bit_rate,
+            sample_type: PhantomData,
+        })
+    }
+}
+
+pub struct SampleBlocks<'a, R, T> {
+    inner: &'a mut R,
+    buf: Vec<u8>,
+    filled: usize,
+    remaining: u64,
+    frame_size: usize,
+    bit_rate: i16,
+    sample_type: PhantomData<fn() -> T>,
+}
+
+impl<'a, R: AsyncRead + Unpin, T: SampleType> Stream
+    for SampleBlocks<'a, R, T>
+{
+    type Item = Result<Vec<T>, ChunkError>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let want = (this.buf.len() as u64).min(this.remaining) as usize;
+
+        while this.filled < want {
+            let mut buf = ReadBuf::new(&mut this.buf[this.filled..want]);
+            if let Err(e) =
+                ready!(Pin::new(&mut *this.inner).poll_read(cx, &mut buf))
+            {
+                return Poll::Ready(Some(Err(e.into())));
+            }
+
+            match buf.filled().len() {
+                // truncated, whatever was read is the last block
+                0 => {
+                    this.remaining = this.filled as u64;
+                    break;
+                }
+                n => this.filled += n,
+            }
+        }
+
+        let len = this.filled / this.frame_size * this.frame_size;
+        this.remaining -= this.filled as u64;
+        this.filled = 0;
+        if len == 0 {
+            return Poll::Ready(None);
+        }
+
+        let mut samples = vec![];
+        stream::decode_samples(&this.buf[..len], this.bit_rate, &mut samples);
+        Poll::Ready(Some(Ok(samples)))
+    }
+}
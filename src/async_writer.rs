@@ -0,0 +1,172 @@
+// async encoder over tokio's AsyncWrite, behind the `tokio` feature. sample
+// blocks are written straight through as they arrive, so the sizes in the
+// header aren't known until the end. `close` goes back and fills them in on
+// seekable sinks. `finish` leaves them at 0, so the file only reads back with
+// ReaderOptions::recovery on, or once `repair` has filled the sizes in -
+// without recovery the readers here see an empty FORM and sound data.
+//
+//     let mut writer = AsyncAiffWriter::new(file, 2, 16, 48000.);
+//     while let Some(block) = incoming.next().await {
+//         writer.write_samples(&block).await?;
+//     }
+//     writer.close().await?;
+use super::{
+    chunks::{CommonChunk, WriteChunk},
+    ids,
+    samples::EncodeSample,
+    walk,
+};
+use std::io::{self, SeekFrom};
+use tokio::io::{AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+// FORM header, COMM chunk, SSND header with its offset and block size
+const HEADER_LEN: u64 = 12 + 8 + CommonChunk::BODY_SIZE as u64 + 16;
+// where the size fields are, for patching
+const FORM_SIZE_POS: u64 = 4;
+const FRAMES_POS: u64 = 12 + 8 + 2;
+const SOUND_SIZE_POS: u64 = 12 + 8 + CommonChunk::BODY_SIZE as u64 + 4;
+
+pub struct AsyncAiffWriter<W> {
+    sink: W,
+    common: CommonChunk,
+    header_written: bool,
+    data_len: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncAiffWriter<W> {
+    pub fn new(
+        sink: W,
+        num_channels: i16,
+        bit_rate: i16,
+        sample_rate: f64,
+    ) -> AsyncAiffWriter<W> {
+        AsyncAiffWriter {
+            sink,
+            common: CommonChunk {
+                size: CommonChunk::BODY_SIZE as i32,
+                num_channels,
+                num_sample_frames: 0,
+                bit_rate,
+                sample_rate,
+            },
+            header_written: false,
+            data_len: 0,
+            buf: vec![],
+        }
+    }
+
+    pub fn common(&self) -> &CommonChunk {
+        &self.common
+    }
+
+    fn frame_size(&self) -> u64 {
+        walk::frame_size(&self.common) as u64
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.data_len / self.frame_size().max(1)
+    }
+
+    async fn write_header(&mut self) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(ids::FORM);
+        header.extend_from_slice(&0u32.to_be_bytes());
+        header.extend_from_slice(ids::AIFF);
+
+        header.extend_from_slice(ids::COMMON);
+        header.extend_from_slice(&self.common.size.to_be_bytes());
+        self.common.write_data(&mut header)?;
+
+        header.extend_from_slice(ids::SOUND);
+        header.extend_from_slice(&0u32.to_be_bytes());
+        header.extend_from_slice(&[0; 8]); // offset, block size
+
+        self.sink.write_all(&header).await?;
+        self.header_written = true;
+
+        Ok(())
+    }
+
+    // raw big endian sample frames, written as is
+    pub async fn write_frames(&mut self, data: &[u8]) -> io::Result<()> {
+        if !self.header_written {
+            self.write_header().await?;
+        }
+        if HEADER_LEN + self.data_len + data.len() as u64 > i32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sound data exceeds the maximum chunk size",
+            ));
+        }
+
+        self.sink.write_all(data).await?;
+        self.data_len += data.len() as u64;
+
+        Ok(())
+    }
+
+    // interleaved sample points, encoded at the bit rate given to `new`
+    pub async fn write_samples<T: EncodeSample>(
+        &mut self,
+        samples: &[T],
+    ) -> io::Result<()> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        for s in samples {
+            s.encode(self.common.bit_rate, &mut buf)?;
+        }
+
+        let res = self.write_frames(&buf).await;
+        self.buf = buf;
+        res
+    }
+
+    // ends the sound data without touching the header, for sinks that
+    // can't seek. the sizes and frame count stay 0, so reading the result
+    // needs ReaderOptions::recovery or a `repair` first. use `close` when
+    // the sink can seek
+    pub async fn finish(mut self) -> io::Result<W> {
+        self.end_sound_data().await?;
+        Ok(self.sink)
+    }
+
+    async fn end_sound_data(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.write_header().await?;
+        }
+        if self.data_len % 2 == 1 {
+            self.sink.write_all(&[0]).await?;
+        }
+        self.sink.flush().await
+    }
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> AsyncAiffWriter<W> {
+    // ends the sound data and patches the FORM and SSND sizes and the frame
+    // count, leaving the sink at the end of the file
+    pub async fn close(mut self) -> io::Result<W> {
+        self.end_sound_data().await?;
+
+        let sound_size = 8 + self.data_len as u32;
+        let form_size =
+            HEADER_LEN as u32 - 8 + self.data_len as u32 + sound_size % 2;
+        let frames = self.frames_written() as u32;
+
+        // the header is wherever the sink was when writing started
+        let end = self.sink.seek(SeekFrom::Current(0)).await?;
+        let start = end - (8 + form_size as u64);
+        for (pos, value) in &[
+            (FORM_SIZE_POS, form_size),
+            (FRAMES_POS, frames),
+            (SOUND_SIZE_POS, sound_size),
+        ] {
+            self.sink.seek(SeekFrom::Start(start + pos)).await?;
+            self.sink.write_all(&value.to_be_bytes()).await?;
+        }
+        self.sink.seek(SeekFrom::Start(end)).await?;
+        self.sink.flush().await?;
+
+        Ok(self.sink)
+    }
+}
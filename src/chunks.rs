@@ -1,11 +1,25 @@
-use super::extended::parse_extended_precision_bytes;
+use super::extended::{
+    parse_extended_precision_bytes, to_extended_precision_bytes,
+};
 use super::{
+    aes::AESChannelStatus,
+    application::ApplicationData,
+    custom::CustomChunk,
+    detect,
+    diagnostics::Diagnostic,
     ids::{self, ChunkID},
+    macroman::{self, TextDecoding},
+    midi::{self, MidiMessage},
+    options::ReaderOptions,
+    probe,
     reader::{self, Buffer},
+    walk, writer,
 };
 use id3;
-use std::io::{Read, Seek, SeekFrom};
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::ops::Div;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub enum ChunkError {
@@ -14,6 +28,26 @@ pub enum ChunkError {
     InvalidID3Version([u8; 2]),
     InvalidSize(i32, i32),     // expected, got,
     InvalidData(&'static str), // failed to parse something
+    InvalidMarkerId(MarkerId), // marker ids must be > 0
+    DuplicateMarkerId(MarkerId),
+    MarkerOutOfRange(MarkerId, u32), // id, position
+    MarkerNotFound(MarkerId),
+    MarkerInUse(MarkerId), // still referenced by COMT or INST
+    InvalidTextByte(u8),   // control character in text
+    UnencodableChar(char), // no MacRoman equivalent
+    InvalidCRC(u8, u8),    // expected, got
+    SoundBeforeCommon,     // SSND before COMM in a forward-only stream
+    ChunkTooLarge(ChunkID, u64), // over the reader's size limit
+    NegativeSize(ChunkID, i32),
+    ChunkPastEnd(ChunkID, u64, u64), // declared size, bytes left
+    Malformed(Diagnostic),       // found by a strict reader
+    Io(io::Error),
+}
+
+impl From<io::Error> for ChunkError {
+    fn from(e: io::Error) -> Self {
+        ChunkError::Io(e)
+    }
 }
 
 // TODO rename 'build'
@@ -21,13 +55,75 @@ pub trait Chunk<'a> {
     fn parse(
         buffer: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<Self>, ChunkError>
     where
         Self: Sized + 'a;
 }
 
+// serializes everything after the chunk id and size. the writer adds those,
+// along with the pad byte for odd sized chunks
+pub trait WriteChunk {
+    fn id(&self) -> ChunkID;
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()>;
+}
+
+fn pad_len(size: i32) -> i64 {
+    (size % 2 != 0) as i64
+}
+
+// moves past a chunk that isn't loaded, from right after its size
+fn skip_body(
+    buf: Buffer<impl Read + Seek>,
+    id: &ChunkID,
+    size: i32,
+) -> Result<(), ChunkError> {
+    if size < 0 {
+        return Err(ChunkError::NegativeSize(*id, size));
+    }
+    buf.seek(SeekFrom::Current(size as i64 + pad_len(size)))?;
+    Ok(())
+}
+
+// checks a declared body size before anything is allocated for it. sizes
+// that are negative, over the reader's limit or longer than the rest of the
+// data are rejected
+fn body_len(
+    buf: Buffer<impl Read + Seek>,
+    id: &ChunkID,
+    size: i32,
+    options: &ReaderOptions,
+) -> Result<usize, ChunkError> {
+    if size < 0 {
+        return Err(ChunkError::NegativeSize(*id, size));
+    }
+    let size = size as u64;
+    options.check_size(id, size)?;
+
+    let pos = buf.position();
+    let left = buf.seek(SeekFrom::End(0))?.saturating_sub(pos);
+    buf.seek(SeekFrom::Start(pos))?;
+    if size > left {
+        return Err(ChunkError::ChunkPastEnd(*id, size, left));
+    }
+
+    Ok(size as usize)
+}
+
+// checks a chunk of `len` bytes can hold the 2 byte count of `count`
+// entries at least `entry_size` bytes each, so a bogus count isn't allocated
+// for
+fn check_count(len: usize, count: u16, entry_size: usize) -> Result<(), ChunkError> {
+    let needed = 2 + count as usize * entry_size;
+    if needed > len {
+        return Err(ChunkError::InvalidSize(needed as i32, len as i32));
+    }
+
+    Ok(())
+}
+
 // TODO different form chunks based on parsing options? lighter weight
 // can a macro help make this dynamic / implement every possible version?
 // CompletedFormChunk, with only required props
@@ -35,6 +131,8 @@ pub trait Chunk<'a> {
 #[derive(Debug)]
 pub struct FormChunk {
     // size: i32,                     // required
+    form_type: ChunkID,            // AIFF or AIFC
+    compression: Option<probe::Compression>, // AIFF-C only
     common: Option<CommonChunk>,   // required
     sound: Option<SoundDataChunk>, // required if num_sample_frames > 0
     comments: Option<CommentsChunk>,
@@ -44,9 +142,46 @@ pub struct FormChunk {
     markers: Option<Vec<MarkerChunk>>,
     midi: Option<Vec<MIDIDataChunk>>,
     apps: Option<Vec<ApplicationSpecificChunk>>,
+    custom: Option<Vec<CustomChunk>>,
+    raw: Option<Vec<RawChunk>>, // chunks with no parser, kept for writing
 }
 
 impl FormChunk {
+    pub fn new() -> FormChunk {
+        FormChunk {
+            form_type: *ids::AIFF,
+            compression: None,
+            common: None,
+            sound: None,
+            comments: None,
+            instrument: None,
+            recording: None,
+            texts: None,
+            markers: None,
+            midi: None,
+            apps: None,
+            custom: None,
+            raw: None,
+        }
+    }
+
+    pub fn form_type(&self) -> &ChunkID {
+        &self.form_type
+    }
+
+    pub fn set_form_type(&mut self, form_type: ChunkID) {
+        self.form_type = form_type;
+    }
+
+    // the compression type and name from an AIFF-C COMM chunk
+    pub fn compression(&self) -> Option<&probe::Compression> {
+        self.compression.as_ref()
+    }
+
+    pub fn set_compression(&mut self, c: Option<probe::Compression>) {
+        self.compression = c;
+    }
+
     pub fn common(&self) -> &Option<CommonChunk> {
         &self.common
     }
@@ -63,18 +198,79 @@ impl FormChunk {
         self.sound = Some(c);
     }
 
+    pub fn comments(&self) -> &Option<CommentsChunk> {
+        &self.comments
+    }
+
     pub fn set_comments(&mut self, c: CommentsChunk) {
         self.comments = Some(c)
     }
 
+    // refuses comments pinned to a marker that doesn't exist
+    pub fn add_comment(&mut self, comment: Comment) -> Result<(), ChunkError> {
+        if let Some(id) = comment.linked_marker() {
+            if self.markers().get(id).is_none() {
+                return Err(ChunkError::MarkerNotFound(id));
+            }
+        }
+
+        let chunk = self
+            .comments
+            .get_or_insert_with(|| CommentsChunk::new(vec![]));
+        chunk.comments.push(comment);
+        chunk.update_size();
+
+        Ok(())
+    }
+
+    pub fn comments_for_marker(&self, id: MarkerId) -> Vec<&Comment> {
+        self.comments
+            .iter()
+            .flat_map(|c| c.comments.iter())
+            .filter(|c| c.marker_id == id)
+            .collect()
+    }
+
+    pub fn instrument(&self) -> &Option<InstrumentChunk> {
+        &self.instrument
+    }
+
     pub fn set_instrument(&mut self, c: InstrumentChunk) {
         self.instrument = Some(c)
     }
 
+    pub fn instrument_mut(&mut self) -> Option<&mut InstrumentChunk> {
+        self.instrument.as_mut()
+    }
+
+    // the INST sustain and release loops as frame positions, via MARK
+    pub fn instrument_loops(
+        &self,
+    ) -> Option<Result<InstrumentLoops, ChunkError>> {
+        let inst = self.instrument.as_ref()?;
+        Some(inst.resolve_loops(&self.markers()))
+    }
+
+    pub fn recording(&self) -> &Option<AudioRecordingChunk> {
+        &self.recording
+    }
+
     pub fn set_recording(&mut self, c: AudioRecordingChunk) {
         self.recording = Some(c)
     }
 
+    pub fn marker_chunks(&self) -> &Option<Vec<MarkerChunk>> {
+        &self.markers
+    }
+
+    pub fn midi_chunks(&self) -> &Option<Vec<MIDIDataChunk>> {
+        &self.midi
+    }
+
+    pub fn app_chunks(&self) -> &Option<Vec<ApplicationSpecificChunk>> {
+        &self.apps
+    }
+
     pub fn add_text_chunk(&mut self, c: TextChunk) {
         if self.texts.is_none() {
             self.texts = Some(vec![]);
@@ -86,6 +282,31 @@ impl FormChunk {
         }
     }
 
+    pub fn texts(&self) -> &Option<Vec<TextChunk>> {
+        &self.texts
+    }
+
+    // first text chunk of the given type, if any
+    pub fn text(&self, chunk_type: TextChunkType) -> Option<&str> {
+        self.texts
+            .as_ref()?
+            .iter()
+            .find(|t| t.chunk_type == chunk_type)
+            .map(|t| t.text.as_str())
+    }
+
+    // replaces every text chunk of `chunk_type` with one chunk per entry in
+    // `texts`. passing an empty slice removes the chunk type entirely
+    pub fn set_texts(&mut self, chunk_type: TextChunkType, texts: &[String]) {
+        let mut chunks = self.texts.take().unwrap_or_default();
+        chunks.retain(|t| t.chunk_type != chunk_type);
+        chunks.extend(texts.iter().map(|t| TextChunk::new(chunk_type, t)));
+
+        if !chunks.is_empty() {
+            self.texts = Some(chunks);
+        }
+    }
+
     pub fn add_marker_chunk(&mut self, c: MarkerChunk) {
         if self.markers.is_none() {
             self.markers = Some(vec![]);
@@ -97,6 +318,174 @@ impl FormChunk {
         }
     }
 
+    // all markers across MARK chunks. a valid file has at most one, but
+    // they're stored as a list to match what's read
+    pub fn markers(&self) -> Markers<'_> {
+        Markers {
+            markers: self
+                .markers
+                .iter()
+                .flatten()
+                .flat_map(|c| c.markers.iter())
+                .collect(),
+            common: self.common.as_ref(),
+        }
+    }
+
+    // markers are edited in a single MARK chunk. if the file had several they
+    // are merged into the first. only call this once the edit is known to
+    // succeed, since it changes the chunks either way
+    fn marker_list_mut(&mut self) -> &mut MarkerChunk {
+        let chunks = self.markers.get_or_insert_with(Vec::new);
+        if chunks.len() != 1 {
+            let merged = chunks.drain(..).flat_map(|c| c.markers).collect();
+            chunks.push(MarkerChunk::new(merged));
+        }
+
+        &mut chunks[0]
+    }
+
+    fn check_marker_position(
+        &self,
+        id: MarkerId,
+        position: u32,
+    ) -> Result<(), ChunkError> {
+        match &self.common {
+            Some(c) if position > c.num_sample_frames => {
+                Err(ChunkError::MarkerOutOfRange(id, position))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // adds a marker with the next unused id, which is returned
+    pub fn add_marker(
+        &mut self,
+        position: u32,
+        name: &str,
+    ) -> Result<MarkerId, ChunkError> {
+        let markers = self.markers();
+        let id = match markers.iter().map(|m| m.id).max() {
+            Some(max) if max < MarkerId::MAX => max + 1,
+            Some(_) => (1..MarkerId::MAX)
+                .find(|id| markers.get(*id).is_none())
+                .ok_or(ChunkError::InvalidMarkerId(MarkerId::MAX))?,
+            None => 1,
+        };
+        self.check_marker_position(id, position)?;
+
+        let chunk = self.marker_list_mut();
+        chunk.markers.push(Marker {
+            id,
+            position,
+            marker_name: name.to_owned(),
+        });
+        chunk.update_size();
+
+        Ok(id)
+    }
+
+    pub fn move_marker(
+        &mut self,
+        id: MarkerId,
+        position: u32,
+    ) -> Result<(), ChunkError> {
+        if self.markers().get(id).is_none() {
+            return Err(ChunkError::MarkerNotFound(id));
+        }
+        self.check_marker_position(id, position)?;
+
+        let chunk = self.marker_list_mut();
+        if let Some(m) = chunk.markers.iter_mut().find(|m| m.id == id) {
+            m.position = position;
+        }
+
+        Ok(())
+    }
+
+    pub fn rename_marker(
+        &mut self,
+        id: MarkerId,
+        name: &str,
+    ) -> Result<(), ChunkError> {
+        if self.markers().get(id).is_none() {
+            return Err(ChunkError::MarkerNotFound(id));
+        }
+
+        let chunk = self.marker_list_mut();
+        if let Some(m) = chunk.markers.iter_mut().find(|m| m.id == id) {
+            m.marker_name = name.to_owned();
+        }
+        chunk.update_size();
+
+        Ok(())
+    }
+
+    // refuses to remove a marker still referenced by a comment or a loop
+    pub fn remove_marker(&mut self, id: MarkerId) -> Result<(), ChunkError> {
+        if self.markers().get(id).is_none() {
+            return Err(ChunkError::MarkerNotFound(id));
+        }
+        if self.is_marker_referenced(id) {
+            return Err(ChunkError::MarkerInUse(id));
+        }
+
+        let chunk = self.marker_list_mut();
+        chunk.markers.retain(|m| m.id != id);
+        chunk.update_size();
+
+        Ok(())
+    }
+
+    pub fn is_marker_referenced(&self, id: MarkerId) -> bool {
+        let in_comments = self
+            .comments
+            .as_ref()
+            .is_some_and(|c| c.comments.iter().any(|c| c.marker_id == id));
+        let in_loops = self.instrument.as_ref().is_some_and(|i| {
+            [&i.sustain_loop, &i.release_loop]
+                .iter()
+                .any(|l| l.begin_loop == id || l.end_loop == id)
+        });
+
+        in_comments || in_loops
+    }
+
+    // reassigns marker ids as 1..n in position order, rewriting the
+    // references in COMT and INST to match. returns (old, new) id pairs
+    pub fn renumber_markers(&mut self) -> Vec<(MarkerId, MarkerId)> {
+        if self.markers().iter().next().is_none() {
+            return vec![];
+        }
+        let chunk = self.marker_list_mut();
+        chunk.markers.sort_by_key(|m| (m.position, m.id));
+
+        let mut mapping = Vec::with_capacity(chunk.markers.len());
+        for (idx, marker) in chunk.markers.iter_mut().enumerate() {
+            let new_id = idx as MarkerId + 1;
+            mapping.push((marker.id, new_id));
+            marker.id = new_id;
+        }
+
+        let remap = |id: &mut MarkerId| {
+            if let Some((_, new)) = mapping.iter().find(|(old, _)| old == id) {
+                *id = *new;
+            }
+        };
+
+        if let Some(c) = &mut self.comments {
+            c.comments.iter_mut().for_each(|c| remap(&mut c.marker_id));
+        }
+        if let Some(i) = &mut self.instrument {
+            for l in [&mut i.sustain_loop, &mut i.release_loop].iter_mut() {
+                remap(&mut l.begin_loop);
+                remap(&mut l.end_loop);
+            }
+        }
+
+        mapping
+    }
+
     pub fn add_midi_chunk(&mut self, c: MIDIDataChunk) {
         if self.midi.is_none() {
             self.midi = Some(vec![]);
@@ -108,6 +497,16 @@ impl FormChunk {
         }
     }
 
+    // the messages of every MIDI chunk, in file order
+    pub fn midi_messages(&self) -> Result<Vec<MidiMessage>, ChunkError> {
+        let mut messages = vec![];
+        for chunk in self.midi.iter().flatten() {
+            messages.extend(chunk.messages()?);
+        }
+
+        Ok(messages)
+    }
+
     pub fn add_app_chunk(&mut self, c: ApplicationSpecificChunk) {
         if self.apps.is_none() {
             self.apps = Some(vec![]);
@@ -119,6 +518,79 @@ impl FormChunk {
         }
     }
 
+    pub fn app_chunks_by_signature<'a>(
+        &'a self,
+        signature: &'a ChunkID,
+    ) -> impl Iterator<Item = &'a ApplicationSpecificChunk> + 'a {
+        self.apps
+            .iter()
+            .flatten()
+            .filter(move |a| &a.application_signature == signature)
+    }
+
+    pub fn remove_app_chunks(&mut self, signature: &ChunkID) {
+        if let Some(apps) = &mut self.apps {
+            apps.retain(|a| &a.application_signature != signature);
+        }
+    }
+
+    // replaces the chunk(s) the value takes the place of, see
+    // `ApplicationData::replaces`
+    pub fn set_app_data(&mut self, data: &dyn ApplicationData) {
+        if let Some(apps) = &mut self.apps {
+            apps.retain(|a| !data.replaces(a));
+        }
+        self.add_app_chunk(ApplicationSpecificChunk::from_data(data));
+    }
+
+    pub fn custom_chunks(&self) -> &Option<Vec<CustomChunk>> {
+        &self.custom
+    }
+
+    pub fn add_custom_chunk(&mut self, c: CustomChunk) {
+        self.custom.get_or_insert_with(Vec::new).push(c);
+    }
+
+    // the value of the first chunk with the id, if it has type T
+    pub fn custom_chunk<T: 'static>(&self, id: &ChunkID) -> Option<&T> {
+        self.custom
+            .iter()
+            .flatten()
+            .find(|c| &c.id == id)
+            .and_then(|c| c.get::<T>())
+    }
+
+    pub fn custom_chunk_mut<T: 'static>(
+        &mut self,
+        id: &ChunkID,
+    ) -> Option<&mut T> {
+        self.custom
+            .iter_mut()
+            .flatten()
+            .find(|c| &c.id == id)
+            .and_then(|c| c.get_mut::<T>())
+    }
+
+    pub fn remove_custom_chunks(&mut self, id: &ChunkID) {
+        if let Some(custom) = &mut self.custom {
+            custom.retain(|c| &c.id != id);
+        }
+    }
+
+    pub fn raw_chunks(&self) -> &Option<Vec<RawChunk>> {
+        &self.raw
+    }
+
+    pub fn add_raw_chunk(&mut self, c: RawChunk) {
+        self.raw.get_or_insert_with(Vec::new).push(c);
+    }
+
+    pub fn remove_raw_chunks(&mut self, id: &ChunkID) {
+        if let Some(raw) = &mut self.raw {
+            raw.retain(|c| &c.id != id);
+        }
+    }
+
     pub fn duration(&self) -> Option<f64> {
         if let Some(common) = &self.common {
             Some((common.num_sample_frames as f64).div(common.sample_rate))
@@ -126,13 +598,67 @@ impl FormChunk {
             None
         }
     }
+
+    // every chunk to be written inside the FORM, in the order the writer
+    // emits them. SSND goes last so the metadata can be read without
+    // skipping over the sound data
+    pub(crate) fn write_order(&self) -> Vec<&dyn WriteChunk> {
+        let mut chunks: Vec<&dyn WriteChunk> = vec![];
+        // AIFF-C expects the format version first
+        let (fver, raw): (Vec<&RawChunk>, Vec<&RawChunk>) =
+            self.raw.iter().flatten().partition(|c| &c.id == ids::FVER);
+        for r in fver {
+            chunks.push(r);
+        }
+        if let Some(c) = &self.common {
+            chunks.push(c);
+        }
+        for m in self.markers.iter().flatten() {
+            chunks.push(m);
+        }
+        if let Some(i) = &self.instrument {
+            chunks.push(i);
+        }
+        if let Some(c) = &self.comments {
+            chunks.push(c);
+        }
+        for t in self.texts.iter().flatten() {
+            chunks.push(t);
+        }
+        if let Some(r) = &self.recording {
+            chunks.push(r);
+        }
+        for m in self.midi.iter().flatten() {
+            chunks.push(m);
+        }
+        for a in self.apps.iter().flatten() {
+            chunks.push(a);
+        }
+        for c in self.custom.iter().flatten().filter(|c| c.is_writable()) {
+            chunks.push(c);
+        }
+        for r in raw {
+            chunks.push(r);
+        }
+        if let Some(s) = &self.sound {
+            chunks.push(s);
+        }
+
+        chunks
+    }
+}
+
+impl Default for FormChunk {
+    fn default() -> Self {
+        FormChunk::new()
+    }
 }
 
 impl Chunk<'_> for FormChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        _options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<FormChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -143,43 +669,25 @@ impl Chunk<'_> for FormChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
-        println!("form chunk bytes {}", size);
-
-        if !read_data {
-            buf.seek(SeekFrom::Current(4)).unwrap();
-
-            return Ok(None);
-        }
+        // the container is always read, the options apply to what's in it
+        let _size = reader::read_i32_be(buf)?;
 
         let mut form_type = [0; 4];
-        buf.read_exact(&mut form_type).unwrap();
+        buf.read_exact(&mut form_type)?;
 
         match &form_type {
-            ids::AIFF => Ok(Some(
-                FormChunk {
-                    // size,
-                    common: None,
-                    sound: None,
-                    comments: None,
-                    instrument: None,
-                    recording: None,
-                    texts: None,
-                    markers: None,
-                    midi: None,
-                    apps: None,
-                }
-            )),
-            ids::AIFF_C => {
-                println!("aiff c file detected; unsupported");
-                Err(ChunkError::InvalidFormType(form_type))
+            // the reader reports AIFF-C compression it can't decode
+            ids::AIFF | ids::AIFF_C => {
+                let mut form = FormChunk::new();
+                form.set_form_type(form_type);
+                Ok(Some(form))
             }
             &x => Err(ChunkError::InvalidFormType(x)),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CommonChunk {
     pub size: i32,
     pub num_channels: i16,
@@ -192,7 +700,7 @@ impl Chunk<'_> for CommonChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<CommonChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -203,28 +711,25 @@ impl Chunk<'_> for CommonChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let (size, num_channels, num_sample_frames, bit_rate) = (
-            reader::read_i32_be(buf),
-            reader::read_i16_be(buf),
-            reader::read_u32_be(buf),
-            reader::read_i16_be(buf),
-        );
-
-        if !read_data {
-            buf.seek(SeekFrom::Current(10)).unwrap();
+        let size = reader::read_i32_be(buf)?;
+        if !options.loads(&id) {
+            skip_body(buf, &id, size)?;
 
             return Ok(None)
         }
-        
-        let mut rate_buf = [0; 10]; // 1 bit sign, 15 bits exponent
-        buf.read_exact(&mut rate_buf).unwrap();
 
-        let sample_rate = match parse_extended_precision_bytes(rate_buf) {
-            Ok(s) => s,
-            Err(()) => {
-                return Err(ChunkError::InvalidData("Extended Precision"))
-            }
-        };
+        if size < CommonChunk::BODY_SIZE as i32 {
+            return Err(ChunkError::InvalidSize(CommonChunk::BODY_SIZE as i32, size));
+        }
+
+        let (num_channels, num_sample_frames, bit_rate) = (
+            reader::read_i16_be(buf)?,
+            reader::read_u32_be(buf)?,
+            reader::read_i16_be(buf)?,
+        );
+
+        let mut rate_buf = [0; 10]; // 1 bit sign, 15 bits exponent
+        buf.read_exact(&mut rate_buf)?;
 
         Ok(Some(
             CommonChunk {
@@ -232,12 +737,55 @@ impl Chunk<'_> for CommonChunk {
                 num_channels,
                 num_sample_frames,
                 bit_rate,
-                sample_rate,
+                sample_rate: decode_sample_rate(rate_buf)?,
             }
         ))
     }
 }
 
+fn decode_sample_rate(bytes: [u8; 10]) -> Result<f64, ChunkError> {
+    parse_extended_precision_bytes(bytes)
+        .map_err(|_| ChunkError::InvalidData("Extended Precision"))
+}
+
+impl CommonChunk {
+    pub const BODY_SIZE: usize = 18;
+
+    // decodes the fields every COMM chunk has from the start of its body.
+    // AIFF-C adds the compression type and name after them
+    pub fn decode(size: i32, body: &[u8]) -> Result<CommonChunk, ChunkError> {
+        if body.len() < CommonChunk::BODY_SIZE {
+            return Err(ChunkError::InvalidSize(18, body.len() as i32));
+        }
+
+        let mut rate_buf = [0; 10];
+        rate_buf.copy_from_slice(&body[8..18]);
+
+        Ok(CommonChunk {
+            size,
+            num_channels: i16::from_be_bytes([body[0], body[1]]),
+            num_sample_frames: u32::from_be_bytes([
+                body[2], body[3], body[4], body[5],
+            ]),
+            bit_rate: i16::from_be_bytes([body[6], body[7]]),
+            sample_rate: decode_sample_rate(rate_buf)?,
+        })
+    }
+}
+
+impl WriteChunk for CommonChunk {
+    fn id(&self) -> ChunkID {
+        *ids::COMMON
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.num_channels.to_be_bytes())?;
+        w.write_all(&self.num_sample_frames.to_be_bytes())?;
+        w.write_all(&self.bit_rate.to_be_bytes())?;
+        w.write_all(&to_extended_precision_bytes(self.sample_rate))
+    }
+}
+
 #[derive(Debug)]
 pub struct SoundDataChunk {
     pub size: i32,
@@ -250,7 +798,7 @@ impl Chunk<'_> for SoundDataChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<SoundDataChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -261,22 +809,41 @@ impl Chunk<'_> for SoundDataChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
-        let offset = reader::read_u32_be(buf);
-        let block_size = reader::read_u32_be(buf);
+        let mut size = reader::read_i32_be(buf)?;
+        // an unfinished recording or a file cut short, the sound data runs
+        // to the end of the file
+        let start = buf.position();
+        let end = buf.seek(SeekFrom::End(0))?;
+        buf.seek(SeekFrom::Start(start))?;
+        let past_end = size as u32 as u64 > end - start;
+        if options.recovers() && (walk::is_unsized(size as u32) || past_end) {
+            if !options.loads(&id) {
+                buf.seek(SeekFrom::End(0))?;
+                return Ok(None);
+            }
+            size = i32::try_from(end - start)
+                .map_err(|_| ChunkError::ChunkTooLarge(id, end - start))?;
+        }
+
+        if size < 8 {
+            return Err(ChunkError::InvalidSize(8, size));
+        }
+        let offset = reader::read_u32_be(buf)?;
+        let block_size = reader::read_u32_be(buf)?;
+        let sound_size = size - 8; // account for offset + block size bytes
 
-        if !read_data {
-            buf.seek(SeekFrom::Current(size as i64)).unwrap();
+        if !options.loads(&id) {
+            buf.seek(SeekFrom::Current(sound_size as i64 + pad_len(size)))?;
 
             return Ok(None);
         }
+        let sound_size = body_len(buf, &id, sound_size, options)?;
 
         // TODO some sort of streaming read optimization?
-        // let sound_size = size - 8; // account for offset + block size bytes
-        let mut sound_data = vec![0u8; size as usize];
-        // let mut sound_data = vec![0u8; sound_size as usize];
+        let mut sound_data = vec![0u8; sound_size];
 
-        buf.read_exact(&mut sound_data).unwrap();
+        buf.read_exact(&mut sound_data)?;
+        buf.seek(SeekFrom::Current(pad_len(size)))?;
 
         Ok(Some(
             SoundDataChunk {
@@ -289,26 +856,61 @@ impl Chunk<'_> for SoundDataChunk {
     }
 }
 
-type MarkerId = i16;
-#[derive(Debug)]
+impl WriteChunk for SoundDataChunk {
+    fn id(&self) -> ChunkID {
+        *ids::SOUND
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.offset.to_be_bytes())?;
+        w.write_all(&self.block_size.to_be_bytes())?;
+        w.write_all(&self.sound_data)
+    }
+}
+
+pub type MarkerId = i16;
+#[derive(Debug, Clone, PartialEq)]
 pub struct Marker {
-    id: MarkerId,
-    position: u32,
-    marker_name: String,
+    pub id: MarkerId,
+    // in sample frames. markers sit between frames, so 0 is before the first
+    // frame and num_sample_frames is after the last
+    pub position: u32,
+    pub marker_name: String,
 }
 
 impl Marker {
-    // TODO return result
-    pub fn from_reader<R: Read + Seek>(r: &mut R) -> Marker {
-        let id = reader::read_i16_be(r);
-        let position = reader::read_u32_be(r);
-        let marker_name = reader::read_pstring(r);
+    pub fn seconds(&self, sample_rate: f64) -> f64 {
+        (self.position as f64).div(sample_rate)
+    }
 
-        Marker {
+    // None if the sample rate isn't positive and finite
+    pub fn duration(&self, sample_rate: f64) -> Option<Duration> {
+        probe::checked_frames_duration(self.position, sample_rate)
+    }
+
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.id.to_be_bytes())?;
+        w.write_all(&self.position.to_be_bytes())?;
+        writer::write_pstring(w, &self.marker_name)
+    }
+
+    fn write_size(&self) -> usize {
+        2 + 4 + writer::pstring_size(&self.marker_name)
+    }
+
+    pub fn from_reader<R: Read + Seek>(
+        r: &mut R,
+        decoding: TextDecoding,
+    ) -> Result<Marker, ChunkError> {
+        let id = reader::read_i16_be(r)?;
+        let position = reader::read_u32_be(r)?;
+        let marker_name = reader::read_pstring(r, decoding)?;
+
+        Ok(Marker {
             id,
             position,
             marker_name,
-        }
+        })
     }
 }
 
@@ -319,11 +921,114 @@ pub struct MarkerChunk {
     pub markers: Vec<Marker>,
 }
 
+impl MarkerChunk {
+    pub fn new(markers: Vec<Marker>) -> MarkerChunk {
+        let mut chunk = MarkerChunk {
+            size: 0,
+            num_markers: 0,
+            markers,
+        };
+        chunk.update_size();
+        chunk
+    }
+
+    // keeps size and num_markers in line with the marker list after edits
+    pub fn update_size(&mut self) {
+        self.num_markers = self.markers.len() as u16;
+        self.size = 2 + self
+            .markers
+            .iter()
+            .map(|m| m.write_size() as i32)
+            .sum::<i32>();
+    }
+}
+
+impl WriteChunk for MarkerChunk {
+    fn id(&self) -> ChunkID {
+        *ids::MARKER
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&(self.markers.len() as u16).to_be_bytes())?;
+        for marker in &self.markers {
+            marker.write(w)?;
+        }
+
+        Ok(())
+    }
+}
+
+// read only view over the markers of a form, with the COMM chunk available to
+// convert positions into time
+pub struct Markers<'a> {
+    markers: Vec<&'a Marker>,
+    common: Option<&'a CommonChunk>,
+}
+
+impl<'a> Markers<'a> {
+    pub fn len(&self) -> usize {
+        self.markers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    pub fn get(&self, id: MarkerId) -> Option<&'a Marker> {
+        self.markers.iter().find(|m| m.id == id).copied()
+    }
+
+    // in file order
+    pub fn iter(&self) -> impl Iterator<Item = &'a Marker> + '_ {
+        self.markers.iter().copied()
+    }
+
+    // by position, ties broken by id
+    pub fn sorted(&self) -> Vec<&'a Marker> {
+        let mut sorted = self.markers.clone();
+        sorted.sort_by_key(|m| (m.position, m.id));
+        sorted
+    }
+
+    // None if the marker doesn't exist or there's no COMM chunk
+    pub fn seconds(&self, id: MarkerId) -> Option<f64> {
+        Some(self.get(id)?.seconds(self.common?.sample_rate))
+    }
+
+    // also None if the sample rate isn't positive and finite
+    pub fn duration(&self, id: MarkerId) -> Option<Duration> {
+        self.get(id)?.duration(self.common?.sample_rate)
+    }
+
+    // ids must be unique and positive. positions are only checked when the
+    // COMM chunk is present
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        for (idx, marker) in self.markers.iter().enumerate() {
+            if marker.id <= 0 {
+                return Err(ChunkError::InvalidMarkerId(marker.id));
+            }
+            if self.markers[..idx].iter().any(|m| m.id == marker.id) {
+                return Err(ChunkError::DuplicateMarkerId(marker.id));
+            }
+            if let Some(common) = self.common {
+                if marker.position > common.num_sample_frames {
+                    return Err(ChunkError::MarkerOutOfRange(
+                        marker.id,
+                        marker.position,
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl Chunk<'_> for MarkerChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<MarkerChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -334,17 +1039,22 @@ impl Chunk<'_> for MarkerChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
-        let num_markers = reader::read_u16_be(buf);
+        let size = reader::read_i32_be(buf)?;
+        if !options.loads(&id) {
+            skip_body(buf, &id, size)?;
 
-        // if !read_data {
-        //     buf.seek(pos)
-        // }
+            return Ok(None);
+        }
+
+        let len = body_len(buf, &id, size, options)?;
+        let num_markers = reader::read_u16_be(buf)?;
+        // id, position and an empty name
+        check_count(len, num_markers, 8)?;
         let mut markers = Vec::with_capacity(num_markers as usize);
         // is it worth it to read all markers at once ant create from buf?
         // or does the usage of BufReader make it irrelevant?
         for _ in 0..num_markers {
-            markers.push(Marker::from_reader(buf));
+            markers.push(Marker::from_reader(buf, options.decoding())?);
         }
 
         Ok(Some(
@@ -357,7 +1067,7 @@ impl Chunk<'_> for MarkerChunk {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextChunkType {
     Name,
     Author,
@@ -372,11 +1082,40 @@ pub struct TextChunk {
     pub text: String,
 }
 
+impl TextChunk {
+    pub fn new(chunk_type: TextChunkType, text: &str) -> TextChunk {
+        TextChunk {
+            chunk_type,
+            size: text.chars().count() as i32,
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn id(&self) -> &'static ChunkID {
+        match self.chunk_type {
+            TextChunkType::Name => ids::NAME,
+            TextChunkType::Author => ids::AUTHOR,
+            TextChunkType::Copyright => ids::COPYRIGHT,
+            TextChunkType::Annotation => ids::ANNOTATION,
+        }
+    }
+}
+
+impl WriteChunk for TextChunk {
+    fn id(&self) -> ChunkID {
+        *TextChunk::id(self)
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&writer::encode_text(&self.text))
+    }
+}
+
 impl Chunk<'_> for TextChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<TextChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -391,20 +1130,21 @@ impl Chunk<'_> for TextChunk {
             _ => return Err(ChunkError::InvalidID(id)),
         };
 
-        let size = reader::read_i32_be(buf);
+        let size = reader::read_i32_be(buf)?;
         let buf_pos_offset = if size % 2 > 0 { 1 } else { 0 };
 
-        if !read_data {
-            buf.seek(SeekFrom::Current(size as i64 + buf_pos_offset)).unwrap();
+        if !options.loads(&id) {
+            skip_body(buf, &id, size)?;
 
             return Ok(None);
         }
+        let len = body_len(buf, &id, size, options)?;
 
-        let mut text_bytes = vec![0; size as usize];
-        buf.read_exact(&mut text_bytes).unwrap();
-        let text = String::from_utf8(text_bytes).unwrap();
+        let mut text_bytes = vec![0; len];
+        buf.read_exact(&mut text_bytes)?;
+        let text = macroman::decode(&text_bytes, options.decoding())?;
 
-        buf.seek(SeekFrom::Current(buf_pos_offset)).unwrap();
+        buf.seek(SeekFrom::Current(buf_pos_offset))?;
         // if size % 2 > 0 {
         //     // if odd, pad byte present - skip it
         //     buf.seek(SeekFrom::Current(1)).unwrap();
@@ -420,49 +1160,189 @@ impl Chunk<'_> for TextChunk {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayMode {
+    #[default]
+    NoLooping,
+    Forward,
+    ForwardBackward,
+    // not in the spec. kept so it's written back, but plays like NoLooping
+    Unknown(i16),
+}
+
+impl PlayMode {
+    pub fn from_i16(mode: i16) -> PlayMode {
+        match mode {
+            0 => PlayMode::NoLooping,
+            1 => PlayMode::Forward,
+            2 => PlayMode::ForwardBackward,
+            _ => PlayMode::Unknown(mode),
+        }
+    }
+
+    pub fn to_i16(self) -> i16 {
+        match self {
+            PlayMode::NoLooping => 0,
+            PlayMode::Forward => 1,
+            PlayMode::ForwardBackward => 2,
+            PlayMode::Unknown(mode) => mode,
+        }
+    }
+
+    pub fn is_looping(self) -> bool {
+        matches!(self, PlayMode::Forward | PlayMode::ForwardBackward)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Loop {
-    // 0 no looping / 1 foward loop / 2 forward backward loop - use enum?
-    play_mode: i16,
-    begin_loop: MarkerId,
-    end_loop: MarkerId,
+    pub play_mode: PlayMode,
+    pub begin_loop: MarkerId,
+    pub end_loop: MarkerId,
 }
 
 impl Loop {
-    // TODO return result
-    pub fn from_reader(r: &mut impl Read) -> Loop {
-        let play_mode = reader::read_i16_be(r);
-        let begin_loop = reader::read_i16_be(r);
-        let end_loop = reader::read_i16_be(r);
+    pub fn from_reader(r: &mut impl Read) -> Result<Loop, ChunkError> {
+        let play_mode = PlayMode::from_i16(reader::read_i16_be(r)?);
+        let begin_loop = reader::read_i16_be(r)?;
+        let end_loop = reader::read_i16_be(r)?;
 
-        Loop {
+        Ok(Loop {
             play_mode,
             begin_loop,
             end_loop,
+        })
+    }
+
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.play_mode.to_i16().to_be_bytes())?;
+        w.write_all(&self.begin_loop.to_be_bytes())?;
+        w.write_all(&self.end_loop.to_be_bytes())
+    }
+
+    // looks up the begin and end markers. None when the loop is off or has
+    // an unknown play mode, or when begin isn't before end, which the spec says means the loop is ignored
+    pub fn resolve(
+        &self,
+        markers: &Markers,
+    ) -> Result<Option<LoopPoints>, ChunkError> {
+        if !self.play_mode.is_looping() {
+            return Ok(None);
+        }
+
+        let find = |id| markers.get(id).ok_or(ChunkError::MarkerNotFound(id));
+        let start = find(self.begin_loop)?.position;
+        let end = find(self.end_loop)?.position;
+
+        if start >= end {
+            return Ok(None);
         }
+
+        Ok(Some(LoopPoints {
+            play_mode: self.play_mode,
+            start,
+            end,
+        }))
     }
 }
 
+// a loop in sample frames. start is the first frame of the loop, end is the
+// frame after the last one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopPoints {
+    pub play_mode: PlayMode,
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InstrumentLoops {
+    pub sustain: Option<LoopPoints>,
+    pub release: Option<LoopPoints>,
+}
+
 // midi note value range = 0..127 (? not the full range?)
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct InstrumentChunk {
-    size: i32,
-    base_note: i8,     // MIDI
-    detune: i8,        // -50..50
-    low_note: i8,      // MIDI
-    high_note: i8,     // MIDI
-    low_velocity: i8,  // MIDI
-    high_velocity: i8, // MIDI
-    gain: i16,         // in db
-    sustain_loop: Loop,
-    release_loop: Loop,
+    pub size: i32,
+    pub base_note: i8,     // MIDI
+    pub detune: i8,        // -50..50
+    pub low_note: i8,      // MIDI
+    pub high_note: i8,     // MIDI
+    pub low_velocity: i8,  // MIDI
+    pub high_velocity: i8, // MIDI
+    pub gain: i16,         // in db
+    pub sustain_loop: Loop,
+    pub release_loop: Loop,
+}
+
+impl Default for InstrumentChunk {
+    // middle C across the full key and velocity range, no loops
+    fn default() -> Self {
+        InstrumentChunk {
+            size: 20,
+            base_note: 60,
+            detune: 0,
+            low_note: 0,
+            high_note: 127,
+            low_velocity: 1,
+            high_velocity: 127,
+            gain: 0,
+            sustain_loop: Loop::default(),
+            release_loop: Loop::default(),
+        }
+    }
+}
+
+impl InstrumentChunk {
+    // ratio to resample by so the sound plays at `note`. base_note is the
+    // note the sound plays at unaltered, and detune shifts it by cents
+    pub fn playback_rate(&self, note: u8) -> f64 {
+        let semitones = note as f64 - self.base_note as f64;
+        let cents = semitones * 100f64 + self.detune as f64;
+
+        2f64.powf(cents / 1200f64)
+    }
+
+    pub fn contains_note(&self, note: u8) -> bool {
+        (self.low_note as i16..=self.high_note as i16).contains(&(note as i16))
+    }
+
+    pub fn contains_velocity(&self, velocity: u8) -> bool {
+        (self.low_velocity as i16..=self.high_velocity as i16)
+            .contains(&(velocity as i16))
+    }
+
+    // whether this sound should play for a note on with these values
+    pub fn in_zone(&self, note: u8, velocity: u8) -> bool {
+        self.contains_note(note) && self.contains_velocity(velocity)
+    }
+
+    // linear amplitude factor for the gain in dB
+    pub fn gain_factor(&self) -> f64 {
+        10f64.powf(self.gain as f64 / 20f64)
+    }
+
+    pub fn apply_gain(&self, sample: f32) -> f32 {
+        (sample as f64 * self.gain_factor()) as f32
+    }
+
+    pub fn resolve_loops(
+        &self,
+        markers: &Markers,
+    ) -> Result<InstrumentLoops, ChunkError> {
+        Ok(InstrumentLoops {
+            sustain: self.sustain_loop.resolve(markers)?,
+            release: self.release_loop.resolve(markers)?,
+        })
+    }
 }
 
 impl Chunk<'_> for InstrumentChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<InstrumentChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -473,17 +1353,23 @@ impl Chunk<'_> for InstrumentChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
-        let base_note = reader::read_i8_be(buf);
-        let detune = reader::read_i8_be(buf);
-        let low_note = reader::read_i8_be(buf);
-        let high_note = reader::read_i8_be(buf);
-        let low_velocity = reader::read_i8_be(buf);
-        let high_velocity = reader::read_i8_be(buf);
-        let gain = reader::read_i16_be(buf);
+        let size = reader::read_i32_be(buf)?;
+        if !options.loads(&id) {
+            skip_body(buf, &id, size)?;
 
-        let sustain_loop = Loop::from_reader(buf);
-        let release_loop = Loop::from_reader(buf);
+            return Ok(None);
+        }
+
+        let base_note = reader::read_i8_be(buf)?;
+        let detune = reader::read_i8_be(buf)?;
+        let low_note = reader::read_i8_be(buf)?;
+        let high_note = reader::read_i8_be(buf)?;
+        let low_velocity = reader::read_i8_be(buf)?;
+        let high_velocity = reader::read_i8_be(buf)?;
+        let gain = reader::read_i16_be(buf)?;
+
+        let sustain_loop = Loop::from_reader(buf)?;
+        let release_loop = Loop::from_reader(buf)?;
 
         Ok(Some(
             InstrumentChunk {
@@ -502,17 +1388,68 @@ impl Chunk<'_> for InstrumentChunk {
     }
 }
 
-#[derive(Debug)]
+impl WriteChunk for InstrumentChunk {
+    fn id(&self) -> ChunkID {
+        *ids::INSTRUMENT
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&[
+            self.base_note as u8,
+            self.detune as u8,
+            self.low_note as u8,
+            self.high_note as u8,
+            self.low_velocity as u8,
+            self.high_velocity as u8,
+        ])?;
+        w.write_all(&self.gain.to_be_bytes())?;
+        self.sustain_loop.write(w)?;
+        self.release_loop.write(w)
+    }
+}
+
+// `data` is kept as read so the chunk round trips byte for byte, even when
+// it holds something `midi::parse` rejects
+#[derive(Debug, Clone, PartialEq)]
 pub struct MIDIDataChunk {
-    size: i32,
-    data: Vec<u8>,
+    pub size: i32,
+    pub data: Vec<u8>,
+}
+
+impl MIDIDataChunk {
+    pub fn new(data: Vec<u8>) -> MIDIDataChunk {
+        MIDIDataChunk {
+            size: data.len() as i32,
+            data,
+        }
+    }
+
+    pub fn from_messages(messages: &[MidiMessage]) -> MIDIDataChunk {
+        MIDIDataChunk::new(midi::encode(messages))
+    }
+
+    pub fn messages(&self) -> Result<Vec<MidiMessage>, ChunkError> {
+        midi::parse(&self.data)
+    }
+
+    // the payload of every sysex message, manufacturer id first
+    pub fn sysex_dumps(&self) -> Result<Vec<Vec<u8>>, ChunkError> {
+        Ok(self
+            .messages()?
+            .into_iter()
+            .filter_map(|m| match m {
+                MidiMessage::SysEx { data, .. } => Some(data),
+                _ => None,
+            })
+            .collect())
+    }
 }
 
 impl Chunk<'_> for MIDIDataChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<MIDIDataChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -523,16 +1460,18 @@ impl Chunk<'_> for MIDIDataChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
+        let size = reader::read_i32_be(buf)?;
 
-        if !read_data {
-            buf.seek(SeekFrom::Current(size as i64)).unwrap();
+        if !options.loads(&id) {
+            skip_body(buf, &id, size)?;
 
             return Ok(None);
         }
+        let len = body_len(buf, &id, size, options)?;
 
-        let mut data = vec![0; size as usize];
-        buf.read_exact(&mut data).unwrap();
+        let mut data = vec![0; len];
+        buf.read_exact(&mut data)?;
+        buf.seek(SeekFrom::Current(pad_len(size)))?;
 
         Ok(Some(
             MIDIDataChunk { size, data }
@@ -540,19 +1479,46 @@ impl Chunk<'_> for MIDIDataChunk {
     }
 }
 
-#[derive(Debug)]
+impl WriteChunk for MIDIDataChunk {
+    fn id(&self) -> ChunkID {
+        *ids::MIDI
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.data)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct AudioRecordingChunk {
-    size: i32,
+    pub size: i32,
     // AESChannelStatusData
     // specified in "AES Recommended Practice for Digital Audio Engineering"
-    data: [u8; 24],
+    pub data: [u8; 24],
+}
+
+impl AudioRecordingChunk {
+    pub fn new(status: &AESChannelStatus) -> AudioRecordingChunk {
+        AudioRecordingChunk {
+            size: 24,
+            data: status.encode(),
+        }
+    }
+
+    pub fn channel_status(&self) -> AESChannelStatus {
+        AESChannelStatus::decode(&self.data)
+    }
+
+    pub fn validate_crc(&self) -> Result<(), ChunkError> {
+        AESChannelStatus::validate_crc(&self.data)
+    }
 }
 
 impl Chunk<'_> for AudioRecordingChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<AudioRecordingChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -563,36 +1529,63 @@ impl Chunk<'_> for AudioRecordingChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
+        let size = reader::read_i32_be(buf)?;
         if size != 24 {
             return Err(ChunkError::InvalidSize(24, size));
         }
 
-        if !read_data {
-            buf.seek(SeekFrom::Current(24)).unwrap();
+        if !options.loads(&id) {
+            buf.seek(SeekFrom::Current(24))?;
 
             return Ok(None);
         }
 
         let mut data = [0; 24];
-        buf.read_exact(&mut data).unwrap();
+        buf.read_exact(&mut data)?;
 
         Ok(Some(AudioRecordingChunk { size, data }))
     }
 }
 
-#[derive(Debug)]
+impl WriteChunk for AudioRecordingChunk {
+    fn id(&self) -> ChunkID {
+        *ids::RECORDING
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.data)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ApplicationSpecificChunk {
-    size: i32,
-    application_signature: ChunkID, // TODO check if bytes should be i8
-    data: Vec<i8>,
+    pub size: i32,
+    pub application_signature: ChunkID,
+    pub data: Vec<u8>,
+}
+
+impl ApplicationSpecificChunk {
+    pub fn new(
+        application_signature: ChunkID,
+        data: Vec<u8>,
+    ) -> ApplicationSpecificChunk {
+        ApplicationSpecificChunk {
+            size: 4 + data.len() as i32,
+            application_signature,
+            data,
+        }
+    }
+
+    pub fn from_data(data: &dyn ApplicationData) -> ApplicationSpecificChunk {
+        ApplicationSpecificChunk::new(data.signature(), data.encode())
+    }
 }
 
 impl Chunk<'_> for ApplicationSpecificChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<ApplicationSpecificChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -603,68 +1596,259 @@ impl Chunk<'_> for ApplicationSpecificChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
-        let application_signature = reader::read_chunk_id(buf); // TODO verify
-        
-        if !read_data {
-            buf.seek(SeekFrom::Current((size - 4) as i64)).unwrap();
+        let size = reader::read_i32_be(buf)?;
+        // the signature is part of the size
+        if size < 4 {
+            return Err(ChunkError::InvalidSize(4, size));
+        }
+        let application_signature = reader::read_chunk_id(buf)?; // TODO verify
+        // odd sized chunks are followed by a pad byte
+        let pad = (size % 2 == 1) as i64;
+
+        if !options.loads(&id) {
+            buf.seek(SeekFrom::Current((size - 4) as i64 + pad))?;
 
             return Ok(None);
         }
-        
-        let mut data = vec![0; (size - 4) as usize]; // account for sig size
-        buf.read_exact(&mut data).unwrap();
+        let len = body_len(buf, &id, size - 4, options)?;
+
+        let mut data = vec![0; len];
+        buf.read_exact(&mut data)?;
+        buf.seek(SeekFrom::Current(pad))?;
 
         Ok(Some(
             ApplicationSpecificChunk {
                 size,
                 application_signature,
-                data: data.iter().map(|byte| i8::from_be_bytes([*byte])).collect(),
+                data,
             }
         ))
     }
 }
 
-#[derive(Debug)]
+impl WriteChunk for ApplicationSpecificChunk {
+    fn id(&self) -> ChunkID {
+        *ids::APPLICATION
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.application_signature)?;
+        w.write_all(&self.data)
+    }
+}
+
+// the body of a chunk the crate has no parser for, such as FVER or a third
+// party chunk. written back as it was read
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawChunk {
+    pub id: ChunkID,
+    pub data: Vec<u8>,
+}
+
+impl Chunk<'_> for RawChunk {
+    fn parse(
+        buf: Buffer<impl Read + Seek>,
+        id: ChunkID,
+        options: &ReaderOptions,
+        curr_buf_pos: &mut Option<u64>
+    ) -> Result<Option<RawChunk>, ChunkError> {
+        if let Some(ref mut pos) = curr_buf_pos {
+            *pos = buf.position();
+        }
+
+        let size = reader::read_i32_be(buf)?;
+        if !options.loads(&id) {
+            skip_body(buf, &id, size)?;
+
+            return Ok(None);
+        }
+        let len = body_len(buf, &id, size, options)?;
+
+        let mut data = vec![0; len];
+        buf.read_exact(&mut data)?;
+        buf.seek(SeekFrom::Current(pad_len(size)))?;
+
+        Ok(Some(RawChunk { id, data }))
+    }
+}
+
+impl WriteChunk for RawChunk {
+    fn id(&self) -> ChunkID {
+        self.id
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.data)
+    }
+}
+
+// seconds between the Mac epoch (1 January 1904) and the unix epoch
+const MAC_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+// timestamps are seconds since 1904, so they can't hold times before it and
+// run out in 2040
+pub fn system_time_to_timestamp(time: SystemTime) -> u32 {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => MAC_EPOCH_OFFSET.saturating_add(d.as_secs()),
+        Err(e) => MAC_EPOCH_OFFSET.saturating_sub(e.duration().as_secs()),
+    };
+    secs.min(u32::MAX as u64) as u32
+}
+
+pub fn timestamp_to_system_time(timestamp: u32) -> SystemTime {
+    let secs = timestamp as u64;
+    if secs >= MAC_EPOCH_OFFSET {
+        UNIX_EPOCH + Duration::from_secs(secs - MAC_EPOCH_OFFSET)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(MAC_EPOCH_OFFSET - secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Comment {
-    timestamp: u32,
-    marker_id: MarkerId,
-    count: u16,
-    text: String, // padded to an even # of bytes
+    pub timestamp: u32,      // seconds since 1 January 1904
+    pub marker_id: MarkerId, // 0 if the comment isn't linked to a marker
+    pub count: u16,
+    pub text: String, // padded to an even # of bytes
 }
 
 impl Comment {
-    // TODO return result
-    pub fn from_reader(r: &mut impl Read) -> Comment {
-        let timestamp = reader::read_u32_be(r);
-        let marker_id = reader::read_i16_be(r);
-        let count = reader::read_u16_be(r);
+    // stamped with the current time
+    pub fn new(text: &str, marker_id: Option<MarkerId>) -> Comment {
+        Comment {
+            timestamp: system_time_to_timestamp(SystemTime::now()),
+            marker_id: marker_id.unwrap_or(0),
+            count: text.chars().count().min(u16::MAX as usize) as u16,
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn time(&self) -> SystemTime {
+        timestamp_to_system_time(self.timestamp)
+    }
+
+    pub fn set_time(&mut self, time: SystemTime) {
+        self.timestamp = system_time_to_timestamp(time);
+    }
+
+    // None for the 0 that means no marker
+    pub fn linked_marker(&self) -> Option<MarkerId> {
+        if self.marker_id == 0 {
+            None
+        } else {
+            Some(self.marker_id)
+        }
+    }
+
+    // the marker the comment is pinned to. None if it isn't linked to one,
+    // or the marker doesn't exist
+    pub fn marker<'a>(&self, markers: &Markers<'a>) -> Option<&'a Marker> {
+        self.linked_marker().and_then(|id| markers.get(id))
+    }
+
+    pub fn from_reader(
+        r: &mut impl Read,
+        decoding: TextDecoding,
+    ) -> Result<Comment, ChunkError> {
+        let timestamp = reader::read_u32_be(r)?;
+        let marker_id = reader::read_i16_be(r)?;
+        let count = reader::read_u16_be(r)?;
 
         let mut str_buf = vec![0; count as usize];
-        r.read_exact(&mut str_buf).unwrap();
-        let text = String::from_utf8(str_buf).unwrap();
+        r.read_exact(&mut str_buf)?;
+        let text = macroman::decode(&str_buf, decoding)?;
 
-        Comment {
+        if count % 2 == 1 {
+            // skip pad byte if odd
+            reader::read_u8(r)?;
+        }
+
+        Ok(Comment {
             timestamp,
             marker_id,
             count,
             text,
+        })
+    }
+
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        let mut text = writer::encode_text(&self.text);
+        text.truncate(u16::MAX as usize);
+        w.write_all(&self.timestamp.to_be_bytes())?;
+        w.write_all(&self.marker_id.to_be_bytes())?;
+        w.write_all(&(text.len() as u16).to_be_bytes())?;
+        w.write_all(&text)?;
+        if text.len() % 2 == 1 {
+            w.write_all(&[0])?;
         }
+
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CommentsChunk {
-    size: i32,
-    num_comments: u16,
-    comments: Vec<Comment>,
+    pub size: i32,
+    pub num_comments: u16,
+    pub comments: Vec<Comment>,
+}
+
+impl CommentsChunk {
+    pub fn new(comments: Vec<Comment>) -> CommentsChunk {
+        let mut chunk = CommentsChunk {
+            size: 0,
+            num_comments: 0,
+            comments,
+        };
+        chunk.update_size();
+        chunk
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    pub fn comments_mut(&mut self) -> &mut Vec<Comment> {
+        &mut self.comments
+    }
+
+    // recomputes size, num_comments and the text counts after editing
+    pub fn update_size(&mut self) {
+        let mut size = 2;
+        for c in self.comments.iter_mut() {
+            let len = c.text.chars().count().min(u16::MAX as usize);
+            c.count = len as u16;
+            size += 8 + len + len % 2;
+        }
+        self.num_comments = self.comments.len() as u16;
+        self.size = size as i32;
+    }
+}
+
+impl WriteChunk for CommentsChunk {
+    fn id(&self) -> ChunkID {
+        *ids::COMMENTS
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&(self.comments.len() as u16).to_be_bytes())?;
+        for comment in &self.comments {
+            comment.write(w)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Chunk<'_> for CommentsChunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<CommentsChunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -675,12 +1859,21 @@ impl Chunk<'_> for CommentsChunk {
             return Err(ChunkError::InvalidID(id));
         }
 
-        let size = reader::read_i32_be(buf);
-        let num_comments = reader::read_u16_be(buf);
+        let size = reader::read_i32_be(buf)?;
+        if !options.loads(&id) {
+            skip_body(buf, &id, size)?;
+
+            return Ok(None);
+        }
+
+        let len = body_len(buf, &id, size, options)?;
+        let num_comments = reader::read_u16_be(buf)?;
+        // timestamp, marker id and an empty text
+        check_count(len, num_comments, 8)?;
 
         let mut comments = Vec::with_capacity(num_comments as usize);
         for _ in 0..num_comments {
-            comments.push(Comment::from_reader(buf))
+            comments.push(Comment::from_reader(buf, options.decoding())?)
         }
 
         Ok(Some(
@@ -724,6 +1917,15 @@ pub struct ID3v2Chunk {
     // // track: Option<u32>,
     // // year: Option<i32>,
     pub tag: id3::Tag,
+    pub version: id3::Version, // from the tag header, to write it back as
+}
+
+fn id3_version(major: u8) -> id3::Version {
+    match major {
+        2 => id3::Version::Id3v22,
+        3 => id3::Version::Id3v23,
+        _ => id3::Version::Id3v24,
+    }
 }
 
 // should this be an optional feature? maybe consumer already has id3 parsing
@@ -731,7 +1933,7 @@ impl Chunk<'_> for ID3v2Chunk {
     fn parse(
         buf: Buffer<impl Read + Seek>,
         id: ChunkID,
-        read_data: bool,
+        options: &ReaderOptions,
         curr_buf_pos: &mut Option<u64>
     ) -> Result<Option<ID3v2Chunk>, ChunkError> {
         if let Some(ref mut pos) = curr_buf_pos {
@@ -743,10 +1945,21 @@ impl Chunk<'_> for ID3v2Chunk {
         }
 
         // TODO is this necessary? can we get this from id3 read
-        let mut version = [0; 2];
-        buf.seek(SeekFrom::Current(3)).unwrap();
-        buf.read_exact(&mut version).unwrap();
-        buf.seek(SeekFrom::Current(-5)).unwrap();
+        let mut header = [0; 10];
+        buf.read_exact(&mut header)?;
+        let version = [header[3], header[4]];
+
+        // a bare tag has no chunk size, the tag header has its own
+        let size = detect::id3_tag_size(&header).unwrap_or(10);
+        if !options.loads(ids::ID3_CHUNK) {
+            buf.seek(SeekFrom::Current(size as i64 - 10))?;
+
+            return Ok(None);
+        }
+        buf.seek(SeekFrom::Current(-10))?;
+        // only the limit, some writers count the header in the tag size so
+        // it can look longer than the data
+        options.check_size(ids::ID3_CHUNK, size as u64)?;
 
         // major versions up to 2.4, no minor versions known
         if version[0] > 4 || version[1] != 0 {
@@ -754,7 +1967,8 @@ impl Chunk<'_> for ID3v2Chunk {
         }
 
         // buffer MUST start with "ID3" or this call will fail
-        let tag = id3::Tag::read_from(buf).unwrap();
+        let tag = id3::Tag::read_from(buf)
+            .map_err(|_| ChunkError::InvalidData("invalid ID3 tag"))?;
         // // let mut _artist = "";
         // // let artist = tag.artist().unwrap().to_owned();
         // // let artist = Some(tag.artist().unwrap_or_default().to_owned());
@@ -828,7 +2042,39 @@ impl Chunk<'_> for ID3v2Chunk {
         Ok(Some(
             ID3v2Chunk {
                 tag,
+                version: id3_version(version[0]),
             }
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seek_bufread::BufReader;
+    use std::io::Cursor;
+
+    fn parse<T: for<'a> Chunk<'a>>(id: &ChunkID, body: &[u8]) -> Result<Option<T>, ChunkError> {
+        let mut bytes = (body.len() as i32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(body);
+        let mut buf = BufReader::new(Cursor::new(bytes));
+        T::parse(&mut buf, *id, &ReaderOptions::new(), &mut None)
+    }
+
+    #[test]
+    fn counts_past_the_chunk() {
+        // one marker with an empty name fits, two don't
+        let mark = [0, 1, 0, 1, 0, 0, 0, 2, 0, 0];
+        assert_eq!(parse::<MarkerChunk>(ids::MARKER, &mark).unwrap().unwrap().markers.len(), 1);
+        let mut mark = mark;
+        mark[1] = 2;
+        assert!(matches!(parse::<MarkerChunk>(ids::MARKER, &mark), Err(ChunkError::InvalidSize(18, 10))));
+
+        let mut comt = vec![0xFF, 0xFF];
+        comt.extend_from_slice(&[0; 8]);
+        assert!(matches!(parse::<CommentsChunk>(ids::COMMENTS, &comt), Err(ChunkError::InvalidSize(524282, 10))));
+        comt[0] = 0;
+        comt[1] = 1;
+        assert_eq!(parse::<CommentsChunk>(ids::COMMENTS, &comt).unwrap().unwrap().comments.len(), 1);
+    }
+}
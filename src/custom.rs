@@ -0,0 +1,114 @@
+// handlers for chunk ids the reader doesn't know about, such as the
+// proprietary chunks DAWs and samplers add. a handler gets a reader limited
+// to the chunk body and returns whatever value it likes; the value is kept
+// on the FormChunk and can be fetched back with its concrete type.
+//
+// chunks whose handler has a write function are serialized again by the
+// writer, the rest are dropped on save
+use super::{
+    chunks::{ChunkError, WriteChunk},
+    ids::ChunkID,
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    io::{self, Read, Write},
+};
+
+// receives the chunk body and its declared size
+pub type CustomChunkParser =
+    fn(&mut dyn Read, u32) -> Result<Box<dyn Any>, ChunkError>;
+
+// writes the chunk body, the id, size and pad byte are added by the writer
+pub type CustomChunkWriter = fn(&dyn Any, &mut dyn Write) -> io::Result<()>;
+
+#[derive(Clone, Copy)]
+pub struct CustomChunkHandler {
+    pub parse: CustomChunkParser,
+    pub write: Option<CustomChunkWriter>,
+}
+
+pub struct CustomChunk {
+    pub id: ChunkID,
+    pub value: Box<dyn Any>,
+    write: Option<CustomChunkWriter>,
+}
+
+impl CustomChunk {
+    pub fn new(
+        id: ChunkID,
+        value: Box<dyn Any>,
+        write: Option<CustomChunkWriter>,
+    ) -> CustomChunk {
+        CustomChunk { id, value, write }
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.value.downcast_ref::<T>()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.value.downcast_mut::<T>()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.write.is_some()
+    }
+}
+
+impl fmt::Debug for CustomChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomChunk")
+            .field("id", &String::from_utf8_lossy(&self.id))
+            .field("writable", &self.is_writable())
+            .finish()
+    }
+}
+
+impl WriteChunk for CustomChunk {
+    fn id(&self) -> ChunkID {
+        self.id
+    }
+
+    fn write_data(&self, w: &mut dyn Write) -> io::Result<()> {
+        match self.write {
+            Some(write) => write(&*self.value, w),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "custom chunk has no write function",
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CustomChunkRegistry {
+    handlers: HashMap<ChunkID, CustomChunkHandler>,
+}
+
+impl CustomChunkRegistry {
+    pub fn new() -> CustomChunkRegistry {
+        CustomChunkRegistry::default()
+    }
+
+    // handlers take precedence over the built in parsers, so a registered
+    // COMM or MARK handler replaces the crate's own
+    pub fn register(
+        &mut self,
+        id: ChunkID,
+        parse: CustomChunkParser,
+        write: Option<CustomChunkWriter>,
+    ) {
+        self.handlers
+            .insert(id, CustomChunkHandler { parse, write });
+    }
+
+    pub fn unregister(&mut self, id: &ChunkID) {
+        self.handlers.remove(id);
+    }
+
+    pub fn get(&self, id: &ChunkID) -> Option<CustomChunkHandler> {
+        self.handlers.get(id).copied()
+    }
+}
@@ -0,0 +1,94 @@
+// format sniffing from the first bytes of a file. AIFF and AIFF-C are
+// recognised from the 12 byte FORM header alone; anything else gets a
+// reason, naming the format when it's a common near miss
+use super::ids::{self, ChunkID};
+
+pub const HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormKind {
+    Aiff,
+    AiffC,
+}
+
+impl FormKind {
+    pub fn form_type(self) -> &'static ChunkID {
+        match self {
+            FormKind::Aiff => ids::AIFF,
+            FormKind::AiffC => ids::AIFF_C,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectError {
+    Empty,
+    // FORM, but fewer than 12 bytes, so the form type is missing
+    TruncatedForm(usize),
+    Iff8SVX, // Amiga 8 bit samples, the closest IFF relative
+    // some other IFF form type, e.g. ILBM images
+    OtherIff(ChunkID),
+    Wave, // RIFF WAVE
+    RF64, // 64 bit WAVE
+    OtherRiff(ChunkID),
+    // an ID3v2 tag with no FORM header, e.g. an MP3. `tag_size` includes
+    // the 10 byte tag header
+    ID3Prefixed { tag_size: u32 },
+    TooShort(usize),  // fewer than 12 bytes and not a FORM prefix
+    Unknown([u8; 4]), // the first 4 bytes
+}
+
+fn chunk_id(bytes: &[u8]) -> ChunkID {
+    let mut id = [0; 4];
+    id.copy_from_slice(&bytes[..4]);
+    id
+}
+
+// size of an ID3v2 tag from its header, sizes are 28 bit "syncsafe" ints
+pub(crate) fn id3_tag_size(bytes: &[u8]) -> Option<u32> {
+    let header = bytes.get(..10)?;
+    let size = header[6..10].iter().try_fold(0u32, |acc, b| match b {
+        b if *b < 0x80 => Some(acc << 7 | *b as u32),
+        _ => None,
+    })?;
+    let footer = if header[5] & 0x10 == 0x10 { 10 } else { 0 };
+
+    Some(10 + size + footer)
+}
+
+pub fn detect(bytes: &[u8]) -> Option<FormKind> {
+    detect_with_reason(bytes).ok()
+}
+
+// like `detect`, but says why the bytes aren't AIFF
+pub fn detect_with_reason(bytes: &[u8]) -> Result<FormKind, DetectError> {
+    if bytes.is_empty() {
+        return Err(DetectError::Empty);
+    }
+
+    if bytes.starts_with(&ids::ID3[..]) {
+        return Err(DetectError::ID3Prefixed {
+            tag_size: id3_tag_size(bytes).unwrap_or(0),
+        });
+    }
+
+    if bytes.len() < HEADER_SIZE {
+        return if ids::FORM.starts_with(&bytes[..bytes.len().min(4)]) {
+            Err(DetectError::TruncatedForm(bytes.len()))
+        } else {
+            Err(DetectError::TooShort(bytes.len()))
+        };
+    }
+
+    let (id, form_type) = (chunk_id(bytes), chunk_id(&bytes[8..]));
+    match (&id, &form_type) {
+        (ids::FORM, ids::AIFF) => Ok(FormKind::Aiff),
+        (ids::FORM, ids::AIFF_C) => Ok(FormKind::AiffC),
+        (ids::FORM, b"8SVX") => Err(DetectError::Iff8SVX),
+        (ids::FORM, _) => Err(DetectError::OtherIff(form_type)),
+        (b"RIFF", b"WAVE") => Err(DetectError::Wave),
+        (b"RF64", _) => Err(DetectError::RF64),
+        (b"RIFF", _) => Err(DetectError::OtherRiff(form_type)),
+        _ => Err(DetectError::Unknown(id)),
+    }
+}
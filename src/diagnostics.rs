@@ -0,0 +1,114 @@
+// problems the reader found and worked around. lenient readers collect
+// them and carry on, strict readers fail with the first one that's more
+// than informational
+use super::ids::ChunkID;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,    // allowed by the spec, e.g. a chunk the reader doesn't know
+    Warning, // malformed, but nothing was lost
+    Error,   // malformed, and some of the data couldn't be read
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    UnknownChunk,
+    // an odd sized chunk followed directly by the next one
+    MissingPadByte,
+    // the declared FORM size against the bytes actually there
+    FormSizeMismatch { declared: u64, actual: u64 },
+    // bytes after the FORM chunk that aren't an ID3 tag
+    TrailingData(u64),
+    ID3v1Tag,
+    // a chunk that runs past the end of the data, with its declared size and
+    // the bytes left for it
+    TruncatedChunk { declared: u32, available: u64 },
+    // the COMM frame count against the frames in SSND
+    FrameCountMismatch { declared: u32, actual: u64 },
+    // failed to parse and was skipped, with the parse error
+    UnreadableChunk(String),
+    // a size of 0 or 0xFFFFFFFF, replaced in recovery mode with the size
+    // the rest of the file gives
+    UnsizedChunk { inferred: u64 },
+    // a COMM frame count of 0, replaced in recovery mode with the frames in
+    // the sound data
+    MissingFrameCount { inferred: u32 },
+    // AIFF-C sound data in a format the reader doesn't decode, e.g. ulaw.
+    // the bytes are loaded as they are
+    UnsupportedCompression(ChunkID),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub offset: u64, // of the chunk id, or wherever the problem starts
+    pub chunk: Option<ChunkID>,
+    pub issue: Issue,
+}
+
+impl Diagnostic {
+    pub fn new(
+        severity: Severity,
+        offset: u64,
+        chunk: Option<ChunkID>,
+        issue: Issue,
+    ) -> Diagnostic {
+        Diagnostic {
+            severity,
+            offset,
+            chunk,
+            issue,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at {}", self.severity, self.offset)?;
+        if let Some(id) = &self.chunk {
+            write!(f, " ({})", String::from_utf8_lossy(id))?;
+        }
+
+        match &self.issue {
+            Issue::UnknownChunk => write!(f, ": unknown chunk"),
+            Issue::MissingPadByte => {
+                write!(f, ": odd sized chunk without a pad byte")
+            }
+            Issue::FormSizeMismatch { declared, actual } => write!(
+                f,
+                ": FORM size is {} but {} bytes follow the header",
+                declared, actual
+            ),
+            Issue::TrailingData(len) => {
+                write!(f, ": {} bytes after the FORM chunk", len)
+            }
+            Issue::ID3v1Tag => write!(f, ": ID3v1 tag, not read"),
+            Issue::TruncatedChunk {
+                declared,
+                available,
+            } => write!(
+                f,
+                ": chunk size is {} but only {} bytes are left",
+                declared, available
+            ),
+            Issue::FrameCountMismatch { declared, actual } => write!(
+                f,
+                ": COMM has {} frames but the sound data holds {}",
+                declared, actual
+            ),
+            Issue::UnreadableChunk(e) => write!(f, ": skipped, {}", e),
+            Issue::UnsizedChunk { inferred } => {
+                write!(f, ": no chunk size, {} bytes assumed", inferred)
+            }
+            Issue::MissingFrameCount { inferred } => {
+                write!(f, ": COMM has no frame count, {} assumed", inferred)
+            }
+            Issue::UnsupportedCompression(c) => write!(
+                f,
+                ": sound data is compressed as '{}' and won't decode",
+                String::from_utf8_lossy(c)
+            ),
+        }
+    }
+}
@@ -67,3 +67,78 @@ pub fn parse_extended_precision_bytes(b: [u8; 10]) -> Result<f64, ()> {
         }
     }
 }
+
+// the inverse of parse_extended_precision_bytes. every finite f64 fits in the
+// 80 bit format, so only the integer bit needs to be made explicit
+pub fn to_extended_precision_bytes(n: f64) -> [u8; 10] {
+    let mut b = [0; 10];
+    if n == 0f64 || !n.is_finite() {
+        return b;
+    }
+
+    let bits = n.abs().to_bits();
+    let exp = ((bits >> 52) & 0x7FF) as i16 - 1023;
+    let significand = (1u64 << 63) | ((bits & ((1u64 << 52) - 1)) << 11);
+
+    let mut exp_bytes = ((exp + EXP_BIAS) as u16).to_be_bytes();
+    if n.is_sign_negative() {
+        exp_bytes[0] |= FIRST_POS;
+    }
+
+    b[..2].copy_from_slice(&exp_bytes);
+    b[2..].copy_from_slice(&significand.to_be_bytes());
+    b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sample rates as they appear in COMM chunks
+    const RATES: [(f64, [u8; 10]); 5] = [
+        (8000., [0x40, 0x0B, 0xFA, 0, 0, 0, 0, 0, 0, 0]),
+        (22050., [0x40, 0x0D, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]),
+        (44100., [0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]),
+        (48000., [0x40, 0x0E, 0xBB, 0x80, 0, 0, 0, 0, 0, 0]),
+        (96000., [0x40, 0x0F, 0xBB, 0x80, 0, 0, 0, 0, 0, 0]),
+    ];
+
+    #[test]
+    fn encodes_sample_rates() {
+        for (rate, bytes) in RATES {
+            assert_eq!(to_extended_precision_bytes(rate), bytes);
+        }
+    }
+
+    #[test]
+    fn sample_rates_round_trip() {
+        for (rate, bytes) in RATES {
+            assert_eq!(parse_extended_precision_bytes(bytes), Ok(rate));
+        }
+        for rate in [11025., 44056., 88200., 176400., 192000.] {
+            let bytes = to_extended_precision_bytes(rate);
+            assert_eq!(parse_extended_precision_bytes(bytes), Ok(rate));
+        }
+    }
+
+    #[test]
+    fn sign_and_fractions() {
+        assert_eq!(
+            to_extended_precision_bytes(-44100.),
+            [0xC0, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            to_extended_precision_bytes(1.5),
+            [0x3F, 0xFF, 0xC0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let bytes = to_extended_precision_bytes(29.97);
+        assert_eq!(parse_extended_precision_bytes(bytes), Ok(29.97));
+    }
+
+    #[test]
+    fn zero_and_non_finite() {
+        assert_eq!(to_extended_precision_bytes(0.), [0; 10]);
+        assert_eq!(to_extended_precision_bytes(f64::NAN), [0; 10]);
+        assert_eq!(parse_extended_precision_bytes([0; 10]), Err(()));
+    }
+}
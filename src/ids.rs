@@ -28,3 +28,4 @@ pub const CATE: &ChunkID = b"cate";
 
 pub const TAG: &[u8; 3] = b"TAG"; // id3 v1
 pub const ID3: &[u8; 3] = b"ID3"; // id3 v2
+pub const ID3_CHUNK: &ChunkID = b"ID3 "; // id3 v2 wrapped in a chunk
@@ -3,10 +3,41 @@ extern crate id3;
 extern crate log;
 extern crate seek_bufread;
 
+pub mod aes;
+pub mod application;
+pub mod artwork;
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+#[cfg(feature = "tokio")]
+pub mod async_writer;
 pub mod chunks;
+pub mod custom;
+pub mod detect;
+pub mod diagnostics;
 mod extended;
 pub mod ids;
+pub mod macroman;
+pub mod metadata;
+pub mod midi;
+pub mod options;
+pub mod probe;
 pub mod reader;
-mod samples;
+pub mod repair;
+pub mod samples;
+pub mod sf2;
+pub mod sfz;
+pub mod slice;
+pub mod stream;
+pub mod validate;
+mod walk;
+pub mod writer;
+
+pub use detect::{detect, detect_with_reason, DetectError, FormKind};
+pub use diagnostics::{Diagnostic, Issue, Severity};
+pub use options::{ChunkLoad, ReaderOptions, Strictness};
+pub use probe::{probe, ProbeInfo};
+pub use repair::{plan_repair, repair, Repair};
+pub use slice::AiffSlice;
+pub use validate::{validate, ValidationReport};
 
 mod test {}
@@ -0,0 +1,179 @@
+// AIFF text (pascal strings, text chunks, comments) predates unicode and is
+// stored as MacRoman. the lower half is ASCII, the upper half maps through
+// this table
+// https://www.unicode.org/Public/MAPPINGS/VENDORS/APPLE/ROMAN.TXT
+use super::chunks::ChunkError;
+use std::borrow::Cow;
+
+#[rustfmt::skip]
+const HIGH_HALF: [u16; 128] = [
+    0x00C4, 0x00C5, 0x00C7, 0x00C9, 0x00D1, 0x00D6, 0x00DC, 0x00E1,
+    0x00E0, 0x00E2, 0x00E4, 0x00E3, 0x00E5, 0x00E7, 0x00E9, 0x00E8,
+    0x00EA, 0x00EB, 0x00ED, 0x00EC, 0x00EE, 0x00EF, 0x00F1, 0x00F3,
+    0x00F2, 0x00F4, 0x00F6, 0x00F5, 0x00FA, 0x00F9, 0x00FB, 0x00FC,
+    0x2020, 0x00B0, 0x00A2, 0x00A3, 0x00A7, 0x2022, 0x00B6, 0x00DF,
+    0x00AE, 0x00A9, 0x2122, 0x00B4, 0x00A8, 0x2260, 0x00C6, 0x00D8,
+    0x221E, 0x00B1, 0x2264, 0x2265, 0x00A5, 0x00B5, 0x2202, 0x2211,
+    0x220F, 0x03C0, 0x222B, 0x00AA, 0x00BA, 0x03A9, 0x00E6, 0x00F8,
+    0x00BF, 0x00A1, 0x00AC, 0x221A, 0x0192, 0x2248, 0x2206, 0x00AB,
+    0x00BB, 0x2026, 0x00A0, 0x00C0, 0x00C3, 0x00D5, 0x0152, 0x0153,
+    0x2013, 0x2014, 0x201C, 0x201D, 0x2018, 0x2019, 0x00F7, 0x25CA,
+    0x00FF, 0x0178, 0x2044, 0x20AC, 0x2039, 0x203A, 0xFB01, 0xFB02,
+    0x2021, 0x00B7, 0x201A, 0x201E, 0x2030, 0x00C2, 0x00CA, 0x00C1,
+    0x00CB, 0x00C8, 0x00CD, 0x00CE, 0x00CF, 0x00CC, 0x00D3, 0x00D4,
+    0xF8FF, 0x00D2, 0x00DA, 0x00DB, 0x00D9, 0x0131, 0x02C6, 0x02DC,
+    0x00AF, 0x02D8, 0x02D9, 0x02DA, 0x00B8, 0x02DD, 0x02DB, 0x02C7,
+];
+
+const REPLACEMENT_BYTE: u8 = b'?';
+
+// every byte has a MacRoman mapping, so decoding only fails on control
+// characters, which don't belong in text and usually mean the bytes aren't
+// text at all. encoding fails on characters MacRoman can't represent.
+//
+// Lossy replaces those with U+FFFD when decoding and '?' when encoding, and
+// drops the trailing NULs some writers use to terminate strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextDecoding {
+    #[default]
+    Lossy,
+    Strict,
+}
+
+fn is_control(b: u8) -> bool {
+    (b < 0x20 && b != b'\t' && b != b'\n' && b != b'\r') || b == 0x7F
+}
+
+pub fn decode(bytes: &[u8], mode: TextDecoding) -> Result<String, ChunkError> {
+    let bytes = match mode {
+        TextDecoding::Lossy => {
+            let end = bytes.iter().rposition(|b| *b != 0).map_or(0, |p| p + 1);
+            &bytes[..end]
+        }
+        TextDecoding::Strict => bytes,
+    };
+
+    let mut text = String::with_capacity(bytes.len());
+    for &b in bytes {
+        let c = match b {
+            b if is_control(b) => match mode {
+                TextDecoding::Lossy => char::REPLACEMENT_CHARACTER,
+                TextDecoding::Strict => {
+                    return Err(ChunkError::InvalidTextByte(b))
+                }
+            },
+            b if b < 0x80 => b as char,
+            b => std::char::from_u32(HIGH_HALF[(b - 0x80) as usize] as u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER),
+        };
+        text.push(c);
+    }
+
+    Ok(text)
+}
+
+// borrows when the bytes are plain ASCII, which MacRoman text usually is
+pub fn decode_cow(
+    bytes: &[u8],
+    mode: TextDecoding,
+) -> Result<Cow<'_, str>, ChunkError> {
+    let trimmed = match mode {
+        TextDecoding::Lossy => {
+            let end = bytes.iter().rposition(|b| *b != 0).map_or(0, |p| p + 1);
+            &bytes[..end]
+        }
+        TextDecoding::Strict => bytes,
+    };
+
+    if trimmed.iter().all(|b| *b < 0x80 && !is_control(*b)) {
+        // ASCII is valid UTF-8
+        return Ok(Cow::Borrowed(std::str::from_utf8(trimmed).unwrap()));
+    }
+
+    decode(bytes, mode).map(Cow::Owned)
+}
+
+pub fn encode_char(c: char) -> Option<u8> {
+    if (c as u32) < 0x80 {
+        return Some(c as u8);
+    }
+
+    HIGH_HALF
+        .iter()
+        .position(|&cp| cp as u32 == c as u32)
+        .map(|idx| idx as u8 + 0x80)
+}
+
+// always one byte per char, so the encoded length is the char count
+pub fn encode(text: &str, mode: TextDecoding) -> Result<Vec<u8>, ChunkError> {
+    text.chars()
+        .map(|c| match (encode_char(c), mode) {
+            (Some(b), _) => Ok(b),
+            (None, TextDecoding::Lossy) => Ok(REPLACEMENT_BYTE),
+            (None, TextDecoding::Strict) => Err(ChunkError::UnencodableChar(c)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_high_half() {
+        let text = decode(b"caf\x8E \xDB5 \xA5", TextDecoding::Strict).unwrap();
+        assert_eq!(text, "café €5 •");
+    }
+
+    #[test]
+    fn high_half_round_trips() {
+        let bytes: Vec<u8> = (0x80..=0xFF).collect();
+        let text = decode(&bytes, TextDecoding::Strict).unwrap();
+        assert_eq!(text.chars().count(), 128);
+        assert_eq!(encode(&text, TextDecoding::Strict).unwrap(), bytes);
+    }
+
+    #[test]
+    fn control_bytes() {
+        assert!(matches!(
+            decode(b"a\x01b", TextDecoding::Strict),
+            Err(ChunkError::InvalidTextByte(0x01))
+        ));
+        assert_eq!(
+            decode(b"a\x01b", TextDecoding::Lossy).unwrap(),
+            "a\u{FFFD}b"
+        );
+        assert_eq!(
+            decode(b"a\tb\r\n", TextDecoding::Strict).unwrap(),
+            "a\tb\r\n"
+        );
+    }
+
+    #[test]
+    fn trailing_nuls() {
+        assert_eq!(decode(b"abc\0\0", TextDecoding::Lossy).unwrap(), "abc");
+        assert!(matches!(
+            decode(b"abc\0", TextDecoding::Strict),
+            Err(ChunkError::InvalidTextByte(0))
+        ));
+        assert_eq!(decode(b"\0\0", TextDecoding::Lossy).unwrap(), "");
+    }
+
+    #[test]
+    fn borrows_ascii() {
+        let text = decode_cow(b"Name\0", TextDecoding::Lossy).unwrap();
+        assert!(matches!(text, Cow::Borrowed("Name")));
+        let text = decode_cow(b"Na\x8Fve", TextDecoding::Lossy).unwrap();
+        assert!(matches!(text, Cow::Owned(ref s) if s == "Naève"));
+    }
+
+    #[test]
+    fn unencodable_chars() {
+        assert_eq!(encode("é€", TextDecoding::Strict).unwrap(), [0x8E, 0xDB]);
+        assert!(matches!(
+            encode("a\u{65E5}", TextDecoding::Strict),
+            Err(ChunkError::UnencodableChar('\u{65E5}'))
+        ));
+        assert_eq!(encode("a\u{65E5}", TextDecoding::Lossy).unwrap(), b"a?");
+    }
+}
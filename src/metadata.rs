@@ -0,0 +1,307 @@
+// a single view over the descriptive metadata an AIFF file can carry. the
+// same field can be stored in several places:
+//
+// - the ID3v2 tag (written by most music library software)
+// - the native NAME / AUTH / (c)  / ANNO text chunks
+// - the native COMT comments chunk
+//
+// when reading, sources are consulted in that order and the first non-empty
+// value wins. ID3 takes precedence because taggers tend to update it and
+// leave the native chunks stale. comments are the exception - they are
+// collected from every source, in the same order, with duplicates merged and
+// each one remembering where it was found.
+//
+// writing a `Metadata` back updates both the native chunks and the ID3 tag so
+// the sources agree again. comments go back to where they came from, and new
+// ones go to both ANNO and ID3. COMT is never rewritten since its comments
+// are tied to markers and timestamps.
+use super::chunks::{FormChunk, TextChunkType};
+use id3::{
+    frame::{Comment, Picture},
+    Tag,
+};
+
+const ID3_COPYRIGHT: &str = "TCOP";
+
+type ID3Getter = fn(&Tag) -> Option<&str>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataSource {
+    ID3v2,
+    TextChunk,
+    CommentsChunk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataField {
+    Title,
+    Artist,
+    Copyright,
+    Comments,
+}
+
+// a field with differing non-empty values across sources
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataConflict {
+    pub field: MetadataField,
+    pub values: Vec<(MetadataSource, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataComment {
+    pub text: String,
+    // where the comment was read from, empty for comments added since
+    pub sources: Vec<MetadataSource>,
+}
+
+impl MetadataComment {
+    pub fn new(text: &str) -> MetadataComment {
+        MetadataComment {
+            text: text.to_owned(),
+            sources: vec![],
+        }
+    }
+
+    // whether writing puts the comment in `source`
+    fn belongs_in(&self, source: MetadataSource) -> bool {
+        self.sources.is_empty() || self.sources.contains(&source)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub copyright: Option<String>,
+    pub comments: Vec<MetadataComment>,
+    // the remaining fields only exist in ID3
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    pub artwork: Vec<Picture>,
+}
+
+impl Metadata {
+    pub fn from_sources(
+        form: Option<&FormChunk>,
+        tag: Option<&Tag>,
+    ) -> Metadata {
+        let native =
+            |t: TextChunkType| form.and_then(|f| f.text(t)).map(str::to_owned);
+        let id3_text = |f: ID3Getter| {
+            tag.and_then(f).filter(|s| !s.is_empty()).map(str::to_owned)
+        };
+
+        let mut comments: Vec<MetadataComment> = vec![];
+        for (source, text) in comment_sources(form, tag) {
+            match comments.iter_mut().find(|c| c.text == text) {
+                Some(c) if !c.sources.contains(&source) => {
+                    c.sources.push(source)
+                }
+                Some(_) => (),
+                None => comments.push(MetadataComment {
+                    text,
+                    sources: vec![source],
+                }),
+            }
+        }
+
+        Metadata {
+            title: id3_text(Tag::title).or_else(|| native(TextChunkType::Name)),
+            artist: id3_text(Tag::artist)
+                .or_else(|| native(TextChunkType::Author)),
+            copyright: id3_text(id3_copyright)
+                .or_else(|| native(TextChunkType::Copyright)),
+            comments,
+            album: id3_text(Tag::album),
+            album_artist: id3_text(Tag::album_artist),
+            genre: id3_text(Tag::genre),
+            year: tag.and_then(Tag::year),
+            track: tag.and_then(Tag::track),
+            artwork: tag
+                .map(|t| t.pictures().cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    // reports every field where the ID3 tag and the native chunks both hold
+    // a value and the values differ. for comments, that's every comment in
+    // only one of ID3 and ANNO when both have some. COMT comments belong to
+    // markers, so they're left out
+    pub fn conflicts(
+        form: Option<&FormChunk>,
+        tag: Option<&Tag>,
+    ) -> Vec<MetadataConflict> {
+        let fields: [(MetadataField, TextChunkType, ID3Getter); 3] = [
+            (MetadataField::Title, TextChunkType::Name, Tag::title),
+            (MetadataField::Artist, TextChunkType::Author, Tag::artist),
+            (
+                MetadataField::Copyright,
+                TextChunkType::Copyright,
+                id3_copyright,
+            ),
+        ];
+
+        let mut conflicts: Vec<MetadataConflict> = fields
+            .iter()
+            .filter_map(|(field, chunk_type, id3_get)| {
+                let from_id3 = tag.and_then(id3_get).filter(|s| !s.is_empty());
+                let from_chunk = form.and_then(|f| f.text(*chunk_type));
+
+                match (from_id3, from_chunk) {
+                    (Some(a), Some(b)) if a != b => Some(MetadataConflict {
+                        field: *field,
+                        values: vec![
+                            (MetadataSource::ID3v2, a.to_owned()),
+                            (MetadataSource::TextChunk, b.to_owned()),
+                        ],
+                    }),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let comments: Vec<(MetadataSource, String)> =
+            comment_sources(form, tag)
+                .into_iter()
+                .filter(|(s, _)| *s != MetadataSource::CommentsChunk)
+                .collect();
+        let has = |source: MetadataSource, text: &str| {
+            comments.iter().any(|(s, t)| *s == source && t == text)
+        };
+        let both = [MetadataSource::ID3v2, MetadataSource::TextChunk]
+            .iter()
+            .all(|source| comments.iter().any(|(s, _)| s == source));
+        let values: Vec<(MetadataSource, String)> = comments
+            .iter()
+            .filter(|(source, text)| {
+                let other = match source {
+                    MetadataSource::ID3v2 => MetadataSource::TextChunk,
+                    _ => MetadataSource::ID3v2,
+                };
+                !has(other, text)
+            })
+            .cloned()
+            .collect();
+        if both && !values.is_empty() {
+            conflicts.push(MetadataConflict {
+                field: MetadataField::Comments,
+                values,
+            });
+        }
+
+        conflicts
+    }
+
+    // syncs every field into the native chunks and the ID3 tag. `None` or
+    // empty fields are removed from both
+    pub fn write_to(&self, form: &mut FormChunk, tag: &mut Tag) {
+        let as_vec =
+            |v: &Option<String>| -> Vec<String> { v.iter().cloned().collect() };
+        form.set_texts(TextChunkType::Name, &as_vec(&self.title));
+        form.set_texts(TextChunkType::Author, &as_vec(&self.artist));
+        form.set_texts(TextChunkType::Copyright, &as_vec(&self.copyright));
+        let annotations: Vec<String> = self
+            .comments
+            .iter()
+            .filter(|c| c.belongs_in(MetadataSource::TextChunk))
+            .map(|c| c.text.clone())
+            .collect();
+        form.set_texts(TextChunkType::Annotation, &annotations);
+
+        match &self.title {
+            Some(t) => tag.set_title(t.as_str()),
+            None => tag.remove_title(),
+        }
+        match &self.artist {
+            Some(a) => tag.set_artist(a.as_str()),
+            None => tag.remove_artist(),
+        }
+        match &self.copyright {
+            Some(c) => tag.set_text(ID3_COPYRIGHT, c.as_str()),
+            None => tag.remove(ID3_COPYRIGHT),
+        }
+        match &self.album {
+            Some(a) => tag.set_album(a.as_str()),
+            None => tag.remove_album(),
+        }
+        match &self.album_artist {
+            Some(a) => tag.set_album_artist(a.as_str()),
+            None => tag.remove_album_artist(),
+        }
+        match &self.genre {
+            Some(g) => tag.set_genre(g.as_str()),
+            None => tag.remove_genre(),
+        }
+        match self.year {
+            Some(y) => tag.set_year(y),
+            None => tag.remove("TYER"),
+        }
+        match self.track {
+            Some(t) => tag.set_track(t),
+            None => tag.remove_track(),
+        }
+
+        tag.remove_comment(None, None);
+        let id3_comments = self
+            .comments
+            .iter()
+            .filter(|c| c.belongs_in(MetadataSource::ID3v2))
+            .map(|c| &c.text);
+        for (idx, text) in id3_comments.enumerate() {
+            // id3 treats comments with the same description as duplicates
+            tag.add_comment(Comment {
+                lang: "eng".to_owned(),
+                description: if idx == 0 {
+                    String::new()
+                } else {
+                    idx.to_string()
+                },
+                text: text.to_owned(),
+            });
+        }
+
+        tag.remove("APIC");
+        for picture in &self.artwork {
+            tag.add_picture(picture.clone());
+        }
+    }
+}
+
+// every non-empty comment with where it's stored, ID3 first
+fn comment_sources(
+    form: Option<&FormChunk>,
+    tag: Option<&Tag>,
+) -> Vec<(MetadataSource, String)> {
+    let mut comments = vec![];
+    if let Some(t) = tag {
+        for c in t.comments() {
+            comments.push((MetadataSource::ID3v2, c.text.clone()));
+        }
+    }
+    if let Some(f) = form {
+        if let Some(texts) = f.texts() {
+            texts
+                .iter()
+                .filter(|t| t.chunk_type == TextChunkType::Annotation)
+                .for_each(|t| {
+                    comments.push((MetadataSource::TextChunk, t.text.clone()))
+                });
+        }
+        if let Some(comt) = f.comments() {
+            for c in comt.comments() {
+                comments
+                    .push((MetadataSource::CommentsChunk, c.text().to_owned()));
+            }
+        }
+    }
+    comments.retain(|(_, text)| !text.is_empty());
+
+    comments
+}
+
+fn id3_copyright(tag: &Tag) -> Option<&str> {
+    tag.get(ID3_COPYRIGHT).and_then(|f| f.content().text())
+}
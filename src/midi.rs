@@ -0,0 +1,485 @@
+// MIDI chunk contents. the spec only says the chunk holds MIDI data, in
+// practice it's sysex dumps (sampler patches, synth settings) and the odd
+// channel message. there is no timing information, so the data is a plain
+// byte stream of messages, possibly using running status
+use super::chunks::ChunkError;
+use std::io::{self, Write};
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOff {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        channel: u8,
+        key: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u8,
+    },
+    PitchBend {
+        channel: u8,
+        value: u16, // 14 bit, 0x2000 is centered
+    },
+    // the bytes between F0 and F7. dumps cut short by another status byte
+    // or the end of the chunk are kept with `terminated` false
+    SysEx {
+        data: Vec<u8>,
+        terminated: bool,
+    },
+    // F1 - F6, status byte followed by its data bytes
+    SystemCommon {
+        status: u8,
+        data: Vec<u8>,
+    },
+    // F8 - FF, single byte messages allowed anywhere, even inside a sysex
+    RealTime(u8),
+}
+
+impl MidiMessage {
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::PolyPressure { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. }
+            | MidiMessage::ChannelPressure { channel, .. }
+            | MidiMessage::PitchBend { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
+    // the 1 or 3 byte manufacturer id at the start of a sysex message
+    pub fn manufacturer_id(&self) -> Option<&[u8]> {
+        match self {
+            MidiMessage::SysEx { data, .. } => match data.first() {
+                Some(0) if data.len() >= 3 => Some(&data[..3]),
+                Some(_) => Some(&data[..1]),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    // encoded without running status, so every message is self contained
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        let status = |kind: u8, channel: u8| kind | (channel & 0x0F);
+
+        match self {
+            MidiMessage::NoteOff {
+                channel,
+                key,
+                velocity,
+            } => w.write_all(&[status(0x80, *channel), *key, *velocity]),
+            MidiMessage::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => w.write_all(&[status(0x90, *channel), *key, *velocity]),
+            MidiMessage::PolyPressure {
+                channel,
+                key,
+                pressure,
+            } => w.write_all(&[status(0xA0, *channel), *key, *pressure]),
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => w.write_all(&[status(0xB0, *channel), *controller, *value]),
+            MidiMessage::ProgramChange { channel, program } => {
+                w.write_all(&[status(0xC0, *channel), *program])
+            }
+            MidiMessage::ChannelPressure { channel, pressure } => {
+                w.write_all(&[status(0xD0, *channel), *pressure])
+            }
+            MidiMessage::PitchBend { channel, value } => w.write_all(&[
+                status(0xE0, *channel),
+                (value & 0x7F) as u8,
+                ((value >> 7) & 0x7F) as u8,
+            ]),
+            MidiMessage::SysEx { data, terminated } => {
+                w.write_all(&[SYSEX_START])?;
+                w.write_all(data)?;
+                if *terminated {
+                    w.write_all(&[SYSEX_END])?;
+                }
+                Ok(())
+            }
+            MidiMessage::SystemCommon { status, data } => {
+                w.write_all(&[*status])?;
+                w.write_all(data)
+            }
+            MidiMessage::RealTime(status) => w.write_all(&[*status]),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.write(&mut bytes).unwrap(); // writing to a vec can't fail
+        bytes
+    }
+}
+
+// number of data bytes following a status byte, None for sysex
+fn data_len(status: u8) -> Option<usize> {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF => Some(2),
+        0xC0..=0xDF => Some(1),
+        0xF1 | 0xF3 => Some(1),
+        0xF2 => Some(2),
+        0xF4..=0xF6 | 0xF8..=0xFF => Some(0),
+        _ => None,
+    }
+}
+
+fn channel_message(status: u8, data: &[u8]) -> MidiMessage {
+    let channel = status & 0x0F;
+
+    match status & 0xF0 {
+        0x80 => MidiMessage::NoteOff {
+            channel,
+            key: data[0],
+            velocity: data[1],
+        },
+        0x90 => MidiMessage::NoteOn {
+            channel,
+            key: data[0],
+            velocity: data[1],
+        },
+        0xA0 => MidiMessage::PolyPressure {
+            channel,
+            key: data[0],
+            pressure: data[1],
+        },
+        0xB0 => MidiMessage::ControlChange {
+            channel,
+            controller: data[0],
+            value: data[1],
+        },
+        0xC0 => MidiMessage::ProgramChange {
+            channel,
+            program: data[0],
+        },
+        0xD0 => MidiMessage::ChannelPressure {
+            channel,
+            pressure: data[0],
+        },
+        _ => MidiMessage::PitchBend {
+            channel,
+            value: data[0] as u16 | (data[1] as u16) << 7,
+        },
+    }
+}
+
+// decodes a MIDI byte stream. channel messages may use running status
+// (data bytes reusing the previous channel status), which system common
+// messages and sysex cancel and real time messages don't affect. real time
+// bytes inside a sysex or between a message's data bytes come out before
+// that message, so encoding the result won't always reproduce the input -
+// the chunk keeps the raw bytes
+pub fn parse(bytes: &[u8]) -> Result<Vec<MidiMessage>, ChunkError> {
+    let mut messages = vec![];
+    let mut running_status: Option<u8> = None;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let b = bytes[pos];
+
+        if b >= 0xF8 {
+            messages.push(MidiMessage::RealTime(b));
+            pos += 1;
+            continue;
+        }
+
+        if b == SYSEX_START {
+            running_status = None;
+            pos += 1;
+
+            let mut data = vec![];
+            let mut terminated = false;
+            while pos < bytes.len() {
+                match bytes[pos] {
+                    SYSEX_END => {
+                        terminated = true;
+                        pos += 1;
+                        break;
+                    }
+                    rt if rt >= 0xF8 => {
+                        messages.push(MidiMessage::RealTime(rt));
+                        pos += 1;
+                    }
+                    // any other status byte ends the dump early
+                    s if s >= 0x80 => break,
+                    d => {
+                        data.push(d);
+                        pos += 1;
+                    }
+                }
+            }
+
+            messages.push(MidiMessage::SysEx { data, terminated });
+            continue;
+        }
+
+        let status = if b >= 0x80 {
+            pos += 1;
+            b
+        } else {
+            running_status
+                .ok_or(ChunkError::InvalidData("MIDI data without status"))?
+        };
+
+        // a lone F7 outside a sysex has nothing to end
+        let len = data_len(status)
+            .ok_or(ChunkError::InvalidData("unexpected MIDI sysex end"))?;
+        // real time bytes can land between the data bytes too
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            match bytes.get(pos) {
+                Some(rt) if *rt >= 0xF8 => {
+                    messages.push(MidiMessage::RealTime(*rt))
+                }
+                Some(d) if *d < 0x80 => data.push(*d),
+                _ => {
+                    return Err(ChunkError::InvalidData(
+                        "truncated MIDI message",
+                    ))
+                }
+            }
+            pos += 1;
+        }
+
+        if status < 0xF0 {
+            running_status = Some(status);
+            messages.push(channel_message(status, &data));
+        } else {
+            running_status = None;
+            messages.push(MidiMessage::SystemCommon { status, data });
+        }
+    }
+
+    Ok(messages)
+}
+
+pub fn encode(messages: &[MidiMessage]) -> Vec<u8> {
+    messages.iter().flat_map(MidiMessage::to_bytes).collect()
+}
+
+fn write_variable_length(w: &mut dyn Write, mut n: u32) -> io::Result<()> {
+    let mut bytes = vec![(n & 0x7F) as u8];
+    n >>= 7;
+    while n > 0 {
+        bytes.push((n & 0x7F) as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.reverse();
+
+    w.write_all(&bytes)
+}
+
+// writes the messages as a format 0 Standard MIDI File. the MIDI chunk has
+// no timing, so events are `ticks_between` ticks apart. sysex uses the F0
+// event form; system common and real time messages aren't valid SMF events,
+// so they go in F7 escapes
+pub fn write_smf(
+    w: &mut impl Write,
+    messages: &[MidiMessage],
+    ticks_per_quarter: u16,
+    ticks_between: u32,
+) -> io::Result<()> {
+    let mut track: Vec<u8> = vec![];
+    for (idx, message) in messages.iter().enumerate() {
+        let delta = if idx == 0 { 0 } else { ticks_between };
+        write_variable_length(&mut track, delta)?;
+
+        match message {
+            MidiMessage::SysEx { data, terminated } => {
+                track.push(SYSEX_START);
+                let len = data.len() + *terminated as usize;
+                write_variable_length(&mut track, len as u32)?;
+                track.extend_from_slice(data);
+                if *terminated {
+                    track.push(SYSEX_END);
+                }
+            }
+            MidiMessage::SystemCommon { .. } | MidiMessage::RealTime(_) => {
+                let bytes = message.to_bytes();
+                track.push(SYSEX_END);
+                write_variable_length(&mut track, bytes.len() as u32)?;
+                track.extend_from_slice(&bytes);
+            }
+            _ => message.write(&mut track)?,
+        }
+    }
+    // end of track
+    track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    w.write_all(b"MThd")?;
+    w.write_all(&6u32.to_be_bytes())?;
+    w.write_all(&0u16.to_be_bytes())?; // format 0
+    w.write_all(&1u16.to_be_bytes())?; // one track
+    w.write_all(&ticks_per_quarter.to_be_bytes())?;
+
+    w.write_all(b"MTrk")?;
+    w.write_all(&(track.len() as u32).to_be_bytes())?;
+    w.write_all(&track)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(key: u8, velocity: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel: 0,
+            key,
+            velocity,
+        }
+    }
+
+    #[test]
+    fn running_status() {
+        let bytes = [0x90, 60, 100, 64, 100, 0x80, 60, 0, 64, 0];
+        let messages = parse(&bytes).unwrap();
+        let off = |key| MidiMessage::NoteOff {
+            channel: 0,
+            key,
+            velocity: 0,
+        };
+        assert_eq!(
+            messages,
+            [note_on(60, 100), note_on(64, 100), off(60), off(64)]
+        );
+    }
+
+    #[test]
+    fn system_common_cancels_running_status() {
+        assert!(parse(&[0x90, 60, 100, 0xF3, 5, 64, 100]).is_err());
+        assert!(parse(&[0x90, 60, 100, 0xF0, 1, 0xF7, 64, 100]).is_err());
+        assert!(parse(&[60, 100]).is_err());
+    }
+
+    #[test]
+    fn real_time_bytes() {
+        // between data bytes and inside a sysex, neither ends the message
+        // or cancels running status
+        let bytes =
+            [0x90, 60, 0xF8, 100, 0xFE, 64, 100, 0xF0, 1, 0xFA, 2, 0xF7];
+        let messages = parse(&bytes).unwrap();
+        assert_eq!(
+            messages,
+            [
+                MidiMessage::RealTime(0xF8),
+                note_on(60, 100),
+                MidiMessage::RealTime(0xFE),
+                note_on(64, 100),
+                MidiMessage::RealTime(0xFA),
+                MidiMessage::SysEx {
+                    data: vec![1, 2],
+                    terminated: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_sysex() {
+        let messages = parse(&[0xF0, 0x41, 0x10, 0x90, 60, 100]).unwrap();
+        assert_eq!(
+            messages,
+            [
+                MidiMessage::SysEx {
+                    data: vec![0x41, 0x10],
+                    terminated: false,
+                },
+                note_on(60, 100),
+            ]
+        );
+        assert!(parse(&[0x90, 60]).is_err());
+    }
+
+    #[test]
+    fn pitch_bend() {
+        let messages = parse(&[0xE3, 0x00, 0x40, 0xE3, 0x7F, 0x7F]).unwrap();
+        assert_eq!(
+            messages,
+            [
+                MidiMessage::PitchBend {
+                    channel: 3,
+                    value: 0x2000,
+                },
+                MidiMessage::PitchBend {
+                    channel: 3,
+                    value: 0x3FFF,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let bytes = [
+            0xC1, 5, 0xB1, 7, 127, 0xF0, 0x7E, 0x7F, 0xF7, 0xF2, 0x10, 0x20,
+            0xF8,
+        ];
+        assert_eq!(encode(&parse(&bytes).unwrap()), bytes);
+    }
+
+    #[test]
+    fn variable_length_quantities() {
+        // the examples from the Standard MIDI File spec
+        let vectors: [(u32, &[u8]); 8] = [
+            (0x00, &[0x00]),
+            (0x40, &[0x40]),
+            (0x7F, &[0x7F]),
+            (0x80, &[0x81, 0x00]),
+            (0x2000, &[0xC0, 0x00]),
+            (0x3FFF, &[0xFF, 0x7F]),
+            (0x4000, &[0x81, 0x80, 0x00]),
+            (0x0FFF_FFFF, &[0xFF, 0xFF, 0xFF, 0x7F]),
+        ];
+        for (n, bytes) in vectors {
+            let mut out = vec![];
+            write_variable_length(&mut out, n).unwrap();
+            assert_eq!(out, bytes);
+        }
+    }
+
+    #[test]
+    fn smf_track() {
+        let mut out = vec![];
+        write_smf(&mut out, &[note_on(60, 100), note_on(60, 0)], 96, 200)
+            .unwrap();
+        assert_eq!(&out[..14], b"MThd\0\0\0\x06\0\0\0\x01\0\x60");
+        assert_eq!(&out[14..22], b"MTrk\0\0\0\x0D");
+        assert_eq!(
+            &out[22..],
+            [
+                0x00, 0x90, 60, 100, 0x81, 0x48, 0x90, 60, 0, 0x00, 0xFF, 0x2F,
+                0x00
+            ]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+// what the readers load, and how forgiving they are. each chunk id is either
+// loaded fully, indexed (its position is recorded so `read_chunk` can load
+// it later) or skipped outright. ids without their own setting use the
+// default, which is Full.
+//
+//     let options = ReaderOptions::new()
+//         .default_load(ChunkLoad::Index)
+//         .load(*ids::COMMON, ChunkLoad::Full)
+//         .load(*ids::APPLICATION, ChunkLoad::Skip)
+//         .max_chunk_size(16 << 20);
+//     let mut reader = AiffReader::with_options(file, options);
+//
+// the ID3 tag is controlled by "ID3 ", whether or not it's wrapped in a
+// chunk
+use super::{chunks::ChunkError, ids::ChunkID, macroman::TextDecoding};
+use std::collections::HashMap;
+
+// 1 GiB, the limit on loaded chunk bodies unless `max_chunk_size` sets one
+pub const DEFAULT_MAX_CHUNK_SIZE: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkLoad {
+    #[default]
+    Full,
+    Index,
+    Skip,
+}
+
+// strict readers fail on the first chunk they can't parse. lenient ones
+// skip the chunk and carry on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    Strict,
+    #[default]
+    Lenient,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReaderOptions {
+    default_load: ChunkLoad,
+    loads: HashMap<ChunkID, ChunkLoad>,
+    strictness: Strictness,
+    text_decoding: TextDecoding,
+    max_chunk_size: Option<u64>,
+    recovery: bool,
+}
+
+impl ReaderOptions {
+    // every chunk loaded, lenient, lossy text, the default size limit and no
+    // recovery
+    pub fn new() -> ReaderOptions {
+        ReaderOptions::default()
+    }
+
+    // positions only, nothing is loaded until `read_chunk`
+    pub fn index_only() -> ReaderOptions {
+        ReaderOptions::new().default_load(ChunkLoad::Index)
+    }
+
+    pub fn default_load(mut self, load: ChunkLoad) -> ReaderOptions {
+        self.default_load = load;
+        self
+    }
+
+    pub fn load(mut self, id: ChunkID, load: ChunkLoad) -> ReaderOptions {
+        self.loads.insert(id, load);
+        self
+    }
+
+    pub fn strictness(mut self, strictness: Strictness) -> ReaderOptions {
+        self.strictness = strictness;
+        self
+    }
+
+    // for NAME, AUTH, (c) , ANNO, marker names and comments
+    pub fn text_decoding(mut self, decoding: TextDecoding) -> ReaderOptions {
+        self.text_decoding = decoding;
+        self
+    }
+
+    // the largest chunk body, in bytes, that will be loaded into memory.
+    // DEFAULT_MAX_CHUNK_SIZE unless set, u64::MAX turns the limit off
+    pub fn max_chunk_size(mut self, size: u64) -> ReaderOptions {
+        self.max_chunk_size = Some(size);
+        self
+    }
+
+    // for files a recorder never finished. FORM and SSND sizes of 0 or
+    // 0xFFFFFFFF are taken to run to the end of the file, and a frame count
+    // of 0 is worked out from the sound data. `repair` fixes the file itself
+    pub fn recovery(mut self, recover: bool) -> ReaderOptions {
+        self.recovery = recover;
+        self
+    }
+
+    pub fn chunk_load(&self, id: &ChunkID) -> ChunkLoad {
+        *self.loads.get(id).unwrap_or(&self.default_load)
+    }
+
+    pub fn loads(&self, id: &ChunkID) -> bool {
+        self.chunk_load(id) == ChunkLoad::Full
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strictness == Strictness::Strict
+    }
+
+    pub fn decoding(&self) -> TextDecoding {
+        self.text_decoding
+    }
+
+    pub fn recovers(&self) -> bool {
+        self.recovery
+    }
+
+    pub fn size_limit(&self) -> u64 {
+        self.max_chunk_size.unwrap_or(DEFAULT_MAX_CHUNK_SIZE)
+    }
+
+    // errors if a body of `size` bytes is over the limit
+    pub fn check_size(
+        &self,
+        id: &ChunkID,
+        size: u64,
+    ) -> Result<(), ChunkError> {
+        if size > self.size_limit() {
+            return Err(ChunkError::ChunkTooLarge(*id, size));
+        }
+
+        Ok(())
+    }
+
+    // the same settings with every chunk loaded, for loading a single
+    // chunk on demand
+    pub(crate) fn loading_all(&self) -> ReaderOptions {
+        ReaderOptions {
+            default_load: ChunkLoad::Full,
+            loads: HashMap::new(),
+            ..self.clone()
+        }
+    }
+}
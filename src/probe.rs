@@ -0,0 +1,173 @@
+// quick inspection of a file's format without parsing it. only the FORM
+// header, the chunk headers, the COMM body and the start of SSND are read,
+// everything else is seeked over, so probing takes the same time for a 10KB
+// file as for a 10GB one
+use super::{
+    chunks::{ChunkError, CommonChunk},
+    ids::{self, ChunkID},
+    macroman::{self, TextDecoding},
+    walk::{self, ChunkWalk},
+};
+use std::{
+    convert::TryInto,
+    io::{Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkHeader {
+    pub id: ChunkID,
+    pub offset: u64, // of the chunk id
+    pub size: u32,   // declared size, without the header or pad byte
+}
+
+impl ChunkHeader {
+    // start of the next chunk, after the pad byte
+    pub fn end(&self) -> u64 {
+        self.offset + 8 + self.size as u64 + self.size as u64 % 2
+    }
+}
+
+// AIFF-C compression, from the end of the COMM chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    pub compression_type: ChunkID, // e.g. NONE, sowt, fl32, ulaw
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeInfo {
+    pub form_type: ChunkID, // AIFF or AIFC
+    pub form_size: u32,
+    pub compression: Option<Compression>, // None for plain AIFF
+    pub channels: i16,
+    pub sample_frames: u32,
+    pub bit_depth: i16,
+    pub sample_rate: f64,
+    pub duration: Duration,
+    // absolute position of the first sample frame, after the SSND offset
+    pub sound_data_offset: Option<u64>,
+    pub sound_data_size: Option<u64>,
+    pub chunks: Vec<ChunkHeader>,
+}
+
+impl ProbeInfo {
+    pub fn is_aifc(&self) -> bool {
+        &self.form_type == ids::AIFF_C
+    }
+}
+
+// duration of `frames` at `sample_rate`, zero when the rate is unusable
+pub(crate) fn frames_duration(frames: u32, sample_rate: f64) -> Duration {
+    checked_frames_duration(frames, sample_rate).unwrap_or_default()
+}
+
+// None for sample rates that aren't positive and finite, or durations too
+// long for a Duration
+pub(crate) fn checked_frames_duration(
+    frames: u32,
+    sample_rate: f64,
+) -> Option<Duration> {
+    if !(sample_rate.is_finite() && sample_rate > 0.) {
+        return None;
+    }
+    Duration::try_from_secs_f64(frames as f64 / sample_rate).ok()
+}
+
+// the AIFF-C compression type and pascal string name following the common
+// COMM fields. None for plain AIFF COMM chunks, which end there
+pub(crate) fn decode_compression(body: &[u8]) -> Option<Compression> {
+    let rest = body.get(CommonChunk::BODY_SIZE..)?;
+    let compression_type: ChunkID = rest.get(..4)?.try_into().ok()?;
+    let name = rest
+        .get(4)
+        .and_then(|len| rest.get(5..5 + *len as usize))
+        .map(|n| macroman::decode(n, TextDecoding::Lossy).unwrap_or_default())
+        .unwrap_or_default();
+
+    Some(Compression {
+        compression_type,
+        name,
+    })
+}
+
+// probes from the start of the source, leaving it positioned wherever
+// probing stopped
+pub fn probe<R: Read + Seek>(r: &mut R) -> Result<ProbeInfo, ChunkError> {
+    r.seek(SeekFrom::Start(0))?;
+
+    let (form_id, form_size) = walk::read_header(r)?
+        .ok_or(ChunkError::InvalidData("missing FORM header"))?;
+    if &form_id != ids::FORM {
+        return Err(ChunkError::InvalidID(form_id));
+    }
+    let mut form_type = [0; 4];
+    r.read_exact(&mut form_type)?;
+    if &form_type != ids::AIFF && &form_type != ids::AIFF_C {
+        return Err(ChunkError::InvalidFormType(form_type));
+    }
+
+    // recorders that crash before finishing leave a 0 size, so fall back
+    // to reading until the end of the file
+    let mut walk = ChunkWalk::new(walk::form_end(form_size, u64::MAX));
+
+    let mut chunks = vec![];
+    let mut common = None;
+    let mut compression = None;
+    let mut sound_data = None;
+
+    while let Some(pos) = walk.next_header() {
+        let (id, size) = match walk::read_header(r)? {
+            Some(h) => h,
+            None => break,
+        };
+        let header = walk.advance(id, size);
+
+        match &id {
+            ids::COMMON if common.is_none() => {
+                let mut body = vec![];
+                r.by_ref()
+                    .take(size.min(walk::COMM_PROBE_LEN) as u64)
+                    .read_to_end(&mut body)?;
+                common = Some(CommonChunk::decode(size as i32, &body)?);
+                compression = decode_compression(&body);
+            }
+            ids::SOUND if sound_data.is_none() => {
+                let mut offsets = [0; 8];
+                r.read_exact(&mut offsets)?;
+                let offset = u32::from_be_bytes([
+                    offsets[0], offsets[1], offsets[2], offsets[3],
+                ]);
+                let start = pos + 16 + offset as u64;
+                // unsized sound data runs to the end of the file
+                let size = match walk::sound_data_len(size, offset) {
+                    u64::MAX => r.seek(SeekFrom::End(0))?.saturating_sub(start),
+                    size => size,
+                };
+                sound_data = Some((start, size));
+            }
+            _ => (),
+        }
+
+        chunks.push(header);
+        if walk.next_header().is_some() {
+            r.seek(SeekFrom::Start(walk.pos()))?;
+        }
+    }
+
+    let common = common.ok_or(ChunkError::InvalidData("missing COMM chunk"))?;
+
+    Ok(ProbeInfo {
+        form_type,
+        form_size,
+        compression,
+        channels: common.num_channels,
+        sample_frames: common.num_sample_frames,
+        bit_depth: common.bit_rate,
+        sample_rate: common.sample_rate,
+        duration: frames_duration(common.num_sample_frames, common.sample_rate),
+        sound_data_offset: sound_data.map(|(start, _)| start),
+        sound_data_size: sound_data.map(|(_, size)| size),
+        chunks,
+    })
+}
@@ -1,10 +1,24 @@
 use super::{
+    application::{ApplicationData, ApplicationDecoder, ApplicationRegistry},
+    artwork::{self, Picture, PictureType},
     chunks::{self, Chunk, FormChunk},
+    custom::{
+        CustomChunk, CustomChunkParser, CustomChunkRegistry, CustomChunkWriter,
+    },
+    detect,
+    diagnostics::{Diagnostic, Issue, Severity},
     ids,
-    samples::SampleType,
+    macroman::{self, TextDecoding},
+    metadata::{Metadata, MetadataConflict},
+    options::{ChunkLoad, ReaderOptions},
+    probe::{self, Compression, ProbeInfo},
+    samples::{LoopingSamples, SampleType},
+    stream,
+    walk,
+    writer::AiffWriter,
 };
 use seek_bufread::BufReader;
-use std::{io::{Read, Seek, SeekFrom}, hash::Hash, convert::TryInto};
+use std::{io::{self, Read, Seek, SeekFrom, Write}, hash::Hash};
 use std::collections::HashMap;
 
 pub type Buffer<'a, Source> = &'a mut BufReader<Source>;
@@ -18,7 +32,12 @@ pub struct AiffReader<Source> {
     // pub id3v1_tags: Vec<chunks::ID3v1Chunk>, // should this be optional? or separate
     // pub id3v2_tags: Vec<chunks::ID3v2Chunk>, // should this be optional? or separate
     pub id3v2_tag: Option<id3::Tag>,
+    id3v2_version: Option<id3::Version>, // as read, the tag is written back the same
     pub form_buf_locations: HashMap<String, u64>,
+    app_registry: ApplicationRegistry,
+    custom_chunks: CustomChunkRegistry,
+    options: ReaderOptions,
+    diagnostics: Vec<Diagnostic>,
 }
 
 pub struct SoundDataMeta {
@@ -28,177 +47,648 @@ pub struct SoundDataMeta {
 
 impl<Source: Read + Seek> AiffReader<Source> {
     pub fn new(s: Source) -> AiffReader<Source> {
+        AiffReader::with_options(s, ReaderOptions::new())
+    }
+
+    pub fn with_options(s: Source, options: ReaderOptions) -> AiffReader<Source> {
         AiffReader {
             buf: BufReader::new(s),
             form_chunk: None,
             id3v2_tag: None,
+            id3v2_version: None,
             form_buf_locations: HashMap::new(),
+            app_registry: ApplicationRegistry::new(),
+            custom_chunks: CustomChunkRegistry::new(),
+            options,
+            diagnostics: vec![],
             // id3v2_tags: vec![],
             // id3v1_tags: vec![],
         }
     }
 
-    pub fn read_all_form_data(&mut self) {
-        self.analyze_data(true, false).unwrap();
+    pub fn options(&self) -> &ReaderOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: ReaderOptions) {
+        self.options = options;
+    }
+
+    // what the last read worked around, empty for well formed files
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
     }
 
+    // loads, indexes and skips chunks as the options say
+    pub fn read(&mut self) -> Result<(), chunks::ChunkError> {
+        self.analyze_data()
+    }
+
+    // read() with every chunk loaded, whatever the options say
+    pub fn read_all_form_data(&mut self) -> Result<(), chunks::ChunkError> {
+        let full = self.options.loading_all();
+        self.read_with(full)
+    }
+
+    // read() with COMM, MARK, INST, COMT and the ID3 tag loaded, as parse
+    // always has, and everything else indexed for read_chunk
     pub fn parse(&mut self) -> Result<(), chunks::ChunkError> {
-        self.analyze_data(false, true).unwrap();
+        let index = self.options.loading_all().default_load(ChunkLoad::Index);
+        let options = [ids::COMMON, ids::MARKER, ids::INSTRUMENT, ids::COMMENTS, ids::ID3_CHUNK]
+            .iter()
+            .fold(index, |o, id| o.load(**id, ChunkLoad::Full));
+        self.read_with(options)
+    }
 
-        Ok(())
+    fn read_with(&mut self, options: ReaderOptions) -> Result<(), chunks::ChunkError> {
+        let options = std::mem::replace(&mut self.options, options);
+        let res = self.analyze_data();
+        self.options = options;
+        res
+    }
+
+    // format details from the chunk headers alone, without parse()
+    pub fn probe(&mut self) -> Result<ProbeInfo, chunks::ChunkError> {
+        probe::probe(&mut self.buf)
     }
 
-    pub fn get_sound_data_metadata(&mut self) -> SoundDataMeta {
-        let sound_data_tag_id = String::from_utf8(ids::SOUND.to_vec()).unwrap();
-        let sound_data_offset = self.form_buf_locations.get(&sound_data_tag_id).unwrap().to_owned();
+    pub fn get_sound_data_metadata(&mut self) -> Result<SoundDataMeta, chunks::ChunkError> {
+        let sound_data_tag_id = String::from_utf8_lossy(ids::SOUND).into_owned();
+        let sound_data_offset = *self.form_buf_locations.get(&sound_data_tag_id)
+            .ok_or(chunks::ChunkError::InvalidData("missing SSND chunk"))?;
 
-        let common = self.read_chunk::<chunks::CommonChunk>(true, false, ids::COMMON).unwrap();
-        
-        SoundDataMeta { 
-            data_offset: sound_data_offset, 
+        let common = self.read_chunk::<chunks::CommonChunk>(ids::COMMON)?
+            .ok_or(chunks::ChunkError::InvalidData("missing COMM chunk"))?;
+
+        Ok(SoundDataMeta {
+            data_offset: sound_data_offset,
             metadata: common,
-        }
+        })
     }
 
-    pub fn read_chunk<'a, T: Chunk<'a> + 'a> (&mut self, read_data: bool, record_form_pos: bool, chunk_id: &[u8]) -> Option<T> {
-        let tag_id = String::from_utf8(chunk_id.to_vec()).unwrap();
-        let mut form_pos = if record_form_pos { Some(0) } else { None };
+    // loads a chunk indexed by read(), or parses at the current position if
+    // it wasn't. parse errors come back as Err where this used to panic, and
+    // Ok(None) means the parser had nothing to load, e.g. an ID3 id with no
+    // tag behind it
+    pub fn read_chunk<'a, T: Chunk<'a> + 'a> (&mut self, chunk_id: &ids::ChunkID) -> Result<Option<T>, chunks::ChunkError> {
+        let tag_id = String::from_utf8_lossy(chunk_id).into_owned();
 
         if let Some(seek_pos) = self.form_buf_locations.get(&tag_id) {
-            self.buf.seek(SeekFrom::Start(*seek_pos)).unwrap();
+            self.buf.seek(SeekFrom::Start(*seek_pos))?;
         }
 
-        let chunk = T::parse(&mut self.buf, chunk_id.try_into().unwrap(), read_data, &mut form_pos).unwrap();
+        let full = self.options.loading_all();
+        let options = std::mem::replace(&mut self.options, full);
+        let chunk = self.parse_chunk(chunk_id);
+        self.options = options;
+
+        chunk
+    }
+
+    // parses at the current position. only the first location of a chunk id
+    // is recorded, so read_chunk always jumps to the first occurrence
+    fn parse_chunk<'a, T: Chunk<'a> + 'a> (&mut self, chunk_id: &ids::ChunkID) -> Result<Option<T>, chunks::ChunkError> {
+        let tag_id = String::from_utf8_lossy(chunk_id).into_owned();
+        let mut form_pos = match self.options.chunk_load(chunk_id) {
+            ChunkLoad::Skip => None,
+            _ => Some(0),
+        };
+
+        let chunk = T::parse(&mut self.buf, *chunk_id, &self.options, &mut form_pos)?;
 
         if let Some(pos) = form_pos {
-            self.form_buf_locations.insert(tag_id, pos);
+            self.form_buf_locations.entry(tag_id).or_insert(pos);
         }
 
-        chunk
+        Ok(chunk)
+    }
+
+    // lenient readers keep the diagnostic, strict ones fail on anything more
+    // than informational
+    fn report(&mut self, diagnostic: Diagnostic) -> Result<(), chunks::ChunkError> {
+        if self.options.is_strict() && diagnostic.severity > Severity::Info {
+            return Err(chunks::ChunkError::Malformed(diagnostic));
+        }
+        self.diagnostics.push(diagnostic);
+
+        Ok(())
+    }
+
+    // a chunk that failed to parse fails the read in strict mode, lenient
+    // readers skip it. io errors always fail
+    fn recover<T>(&mut self, result: Result<Option<T>, chunks::ChunkError>, offset: u64, id: ids::ChunkID) -> Result<Option<T>, chunks::ChunkError> {
+        match result {
+            Err(chunks::ChunkError::Io(e)) => Err(chunks::ChunkError::Io(e)),
+            Err(e) if self.options.is_strict() => Err(e),
+            Err(e) => {
+                let issue = Issue::UnreadableChunk(format!("{:?}", e));
+                self.diagnostics.push(Diagnostic::new(Severity::Error, offset, Some(id), issue));
+                Ok(None)
+            }
+            ok => ok,
+        }
+    }
+
+    fn parse_known<'a, T: Chunk<'a> + 'a>(&mut self, chunk_id: &ids::ChunkID, offset: u64) -> Result<Option<T>, chunks::ChunkError> {
+        let chunk = self.parse_chunk(chunk_id);
+        self.recover(chunk, offset, *chunk_id)
+    }
+
+    // None past the end of the data
+    fn peek(&mut self, at: u64) -> Result<Option<[u8; 4]>, chunks::ChunkError> {
+        self.buf.seek(SeekFrom::Start(at))?;
+        let mut bytes = [0; 4];
+        match self.buf.read_exact(&mut bytes) {
+            Ok(()) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // end of the FORM chunk, no further than the end of the file. a FORM
+    // shorter than the file is fine, ID3 tags are often appended after it
+    fn form_end(&mut self, file_len: u64) -> Result<u64, chunks::ChunkError> {
+        let declared = self.peek(4)?.map_or(0, u32::from_be_bytes) as u64;
+        let actual = file_len.saturating_sub(8);
+
+        if self.options.recovers() && walk::is_unsized(declared as u32) {
+            let issue = Issue::UnsizedChunk { inferred: actual };
+            self.report(Diagnostic::new(Severity::Warning, 0, Some(*ids::FORM), issue))?;
+            return Ok(file_len);
+        }
+
+        // an unfinished recording, nothing can be read without recovery. a
+        // file cut short is recovered up to where it stops
+        if declared > actual || declared == 0 {
+            let severity = if self.options.recovers() && declared > actual { Severity::Warning } else { Severity::Error };
+            let issue = Issue::FormSizeMismatch { declared, actual };
+            self.report(Diagnostic::new(severity, 0, Some(*ids::FORM), issue))?;
+        }
+
+        Ok(8 + declared.min(actual))
     }
 
-    fn analyze_data(&mut self, read_data: bool, record_form_pos: bool) -> Result<(), chunks::ChunkError> {
-        self.buf.rewind().unwrap();
+    // moves the walk past the chunk at its position. odd sized chunks are
+    // followed by a pad byte, but some writers leave it out, which shows as
+    // the next chunk id starting a byte early
+    fn next_chunk(&mut self, chunks: &mut walk::ChunkWalk, id: ids::ChunkID, size: u32, file_len: u64) -> Result<(), chunks::ChunkError> {
+        let header = chunks.advance(id, size);
+        let end = header.offset + 8 + size as u64;
+        if size % 2 == 0 {
+            return Ok(());
+        }
+
+        let missing = end == file_len || {
+            let unpadded = self.peek(end)?;
+            let padded = self.peek(end + 1)?;
+            unpadded.is_some_and(|i| is_plausible_id(&i)) && !padded.is_some_and(|i| is_plausible_id(&i))
+        };
+        if missing {
+            self.report(Diagnostic::new(Severity::Warning, header.offset, Some(id), Issue::MissingPadByte))?;
+            chunks.set_pos(end);
+        }
+
+        Ok(())
+    }
+
+    // past the FORM chunk only tags are expected
+    fn read_trailing(&mut self, mut offset: u64, file_len: u64) -> Result<(), chunks::ChunkError> {
+        while offset + 8 <= file_len {
+            self.buf.seek(SeekFrom::Start(offset))?;
+            let id = match walk::read_header(&mut self.buf)? {
+                Some((id, _)) => id,
+                None => break,
+            };
+
+            if &id[..3] == ids::ID3 && &id != ids::ID3_CHUNK {
+                offset = self.read_bare_id3(offset)?;
+                continue;
+            }
+            if &id[..3] == b"TAG" {
+                self.report(Diagnostic::new(Severity::Info, offset, None, Issue::ID3v1Tag))?;
+                offset += 128;
+                continue;
+            }
+
+            let issue = Issue::TrailingData(file_len - offset);
+            self.report(Diagnostic::new(Severity::Warning, offset, None, issue))?;
+            break;
+        }
+
+        Ok(())
+    }
+
+    // ID3 tags outside a chunk have no chunk size, the tag header has its own.
+    // returns the end of the tag
+    fn read_bare_id3(&mut self, offset: u64) -> Result<u64, chunks::ChunkError> {
+        self.buf.seek(SeekFrom::Start(offset))?;
+        let mut header = [0; 10];
+        self.buf.read_exact(&mut header)?;
+        let tag_size = detect::id3_tag_size(&header).unwrap_or(10);
+
+        self.buf.seek(SeekFrom::Start(offset))?;
+        let chunk = self.parse_chunk::<chunks::ID3v2Chunk>(ids::ID3_CHUNK);
+        if let Some(chunk) = self.recover(chunk, offset, *ids::ID3_CHUNK)? {
+            self.id3v2_tag = Some(chunk.tag);
+            self.id3v2_version = Some(chunk.version);
+        }
+
+        Ok(offset + tag_size as u64)
+    }
+
+    // the AIFF-C fields after the common ones in COMM. compressed data is
+    // loaded as it is, which is reported since samples() can't decode it
+    fn read_compression(&mut self, offset: u64, size: u32) -> Result<Option<Compression>, chunks::ChunkError> {
+        self.buf.seek(SeekFrom::Start(offset + 8))?;
+        let mut body = vec![];
+        self.buf.by_ref().take(size.min(walk::COMM_PROBE_LEN) as u64).read_to_end(&mut body)?;
+        let compression = probe::decode_compression(&body);
+
+        if let Some(c) = compression.as_ref().filter(|c| !stream::is_uncompressed(c)) {
+            let issue = Issue::UnsupportedCompression(c.compression_type);
+            self.report(Diagnostic::new(Severity::Warning, offset, Some(*ids::COMMON), issue))?;
+        }
+
+        Ok(compression)
+    }
 
-        let form_id = read_chunk_id(&mut self.buf);
-        let mut form = match self.read_chunk::<chunks::FormChunk>(true, record_form_pos, &form_id) {
+    // the COMM frame count against the frames SSND actually holds. in
+    // recovery mode a missing count, or one past the end of a truncated
+    // file, is returned to replace the declared one
+    fn check_frame_count(&mut self, common: &chunks::CommonChunk, sound_offset: u64, sound_size: u32, truncated: bool) -> Result<Option<u32>, chunks::ChunkError> {
+        let frame_size = walk::frame_size(common) as u64;
+        if frame_size == 0 {
+            return Ok(None);
+        }
+
+        let data_offset = self.peek(sound_offset + 8)?.map_or(0, u32::from_be_bytes) as u64;
+        let actual = (sound_size as u64).saturating_sub(8 + data_offset) / frame_size;
+        let declared = common.num_sample_frames;
+        if actual == declared as u64 {
+            return Ok(None);
+        }
+
+        if declared == 0 && self.options.recovers() {
+            let inferred = actual.min(u32::MAX as u64) as u32;
+            let issue = Issue::MissingFrameCount { inferred };
+            self.report(Diagnostic::new(Severity::Warning, sound_offset, Some(*ids::SOUND), issue))?;
+            return Ok(Some(inferred));
+        }
+        // the frames that made it into a truncated file are all there is
+        if truncated && actual < declared as u64 && self.options.recovers() {
+            let issue = Issue::FrameCountMismatch { declared, actual };
+            self.report(Diagnostic::new(Severity::Warning, sound_offset, Some(*ids::SOUND), issue))?;
+            return Ok(Some(actual as u32));
+        }
+
+        // frames missing is worse than extra data at the end
+        let severity = if actual < declared as u64 { Severity::Error } else { Severity::Warning };
+        let issue = Issue::FrameCountMismatch { declared, actual };
+        self.report(Diagnostic::new(severity, sound_offset, Some(*ids::SOUND), issue))?;
+        Ok(None)
+    }
+
+    fn analyze_data(&mut self) -> Result<(), chunks::ChunkError> {
+        self.diagnostics.clear();
+        let file_len = self.buf.seek(SeekFrom::End(0))?;
+        self.buf.rewind()?;
+
+        let form_id = read_chunk_id(&mut self.buf)?;
+        let mut form = match self.parse_chunk::<chunks::FormChunk>(&form_id)? {
             Some(item) => item,
             None => return Err(chunks::ChunkError::InvalidData("failed to parse form data"))
         };
+        let form_end = self.form_end(file_len)?;
+
+        let mut chunks = walk::ChunkWalk::new(form_end);
+        let mut sound = None;
+        while let Some(offset) = chunks.next_header() {
+            self.buf.seek(SeekFrom::Start(offset))?;
+            let (id, mut size) = match walk::read_header(&mut self.buf)? {
+                Some(header) => header,
+                None => break,
+            };
 
-        while self.buf.available() >= 4 {
-            let id = read_chunk_id(&mut self.buf);
+            // ID3 chunks aren't stored in the FORM chunk. should they
+            // be stored next to the form chunk in the reader?
+            if &id[..3] == ids::ID3 && &id != ids::ID3_CHUNK {
+                let end = self.read_bare_id3(offset)?;
+                chunks.set_pos(end);
+                continue;
+            }
+
+            let available = file_len - offset - 8;
+
+            // an unfinished recording, the sound data runs to the end of the
+            // file. SoundDataChunk works the size out the same way
+            let unsized_sound = self.options.recovers() && &id == ids::SOUND && walk::is_unsized(size);
+            if unsized_sound {
+                let issue = Issue::UnsizedChunk { inferred: available };
+                self.report(Diagnostic::new(Severity::Warning, offset, Some(id), issue))?;
+                size = available.min(u32::MAX as u64 - 1) as u32;
+            }
+            // a file cut short, recovery reads what's left of the chunk. only
+            // the sound data can be used partly, the other parsers fail on it
+            let truncated = size as u64 > available;
+            if truncated && self.options.recovers() {
+                let issue = Issue::TruncatedChunk { declared: size, available };
+                self.report(Diagnostic::new(Severity::Warning, offset, Some(id), issue))?;
+                if &id == ids::SOUND {
+                    size = available as u32;
+                }
+            } else if truncated {
+                let issue = Issue::TruncatedChunk { declared: size, available };
+                self.report(Diagnostic::new(Severity::Error, offset, Some(id), issue))?;
+                chunks.advance(id, size);
+                break;
+            }
+            // running past the end of the FORM but not the file, the data is
+            // there so it's read anyway
+            let form_left = form_end.saturating_sub(offset + 8);
+            if size as u64 > form_left {
+                let issue = Issue::TruncatedChunk { declared: size, available: form_left };
+                self.report(Diagnostic::new(Severity::Warning, offset, Some(id), issue))?;
+            }
+            // the parsers read the size themselves
+            self.buf.seek(SeekFrom::Start(offset + 4))?;
+
+            if let Some(handler) = self.custom_chunks.get(&id) {
+                let chunk = self.parse_custom_chunk(id, handler.parse, handler.write)?;
+                if let Some(c) = chunk {
+                    form.add_custom_chunk(c);
+                }
+                self.next_chunk(&mut chunks, id, size, file_len)?;
+                continue;
+            }
 
-            // once the common and form are detected, we can loop
             // buffer position is right past the id
             match &id {
                 ids::COMMON => {
-                    // println!("Common chunk detected");
-                    if let Some(common) = self.read_chunk::<chunks::CommonChunk>(read_data, record_form_pos, &id) {
+                    if let Some(common) = self.parse_known::<chunks::CommonChunk>(&id, offset)? {
                         form.set_common(common);
+                        if form.form_type() == ids::AIFF_C {
+                            let compression = self.read_compression(offset, size)?;
+                            form.set_compression(compression);
+                        }
                     }
                 }
                 ids::SOUND => {
-                    if let Some(sound) = self.read_chunk::<chunks::SoundDataChunk>(read_data, record_form_pos, &id) {
+                    sound = Some((offset, size, truncated));
+                    if let Some(sound) = self.parse_known::<chunks::SoundDataChunk>(&id, offset)? {
                         form.set_sound(sound);
                     }
                 }
                 ids::MARKER => {
-                    if let Some(mark) = self.read_chunk::<chunks::MarkerChunk>(read_data, record_form_pos, &id) {
+                    if let Some(mark) = self.parse_known::<chunks::MarkerChunk>(&id, offset)? {
                         form.add_marker_chunk(mark);
                     }
                 }
                 ids::INSTRUMENT => {
-                    if let Some(inst) = self.read_chunk::<chunks::InstrumentChunk>(read_data, record_form_pos, &id) {
+                    if let Some(inst) = self.parse_known::<chunks::InstrumentChunk>(&id, offset)? {
                         form.set_instrument(inst);
                     }
                 }
                 ids::MIDI => {
-                    if let Some(midi) = self.read_chunk::<chunks::MIDIDataChunk>(read_data, record_form_pos, &id) {
+                    if let Some(midi) = self.parse_known::<chunks::MIDIDataChunk>(&id, offset)? {
                         form.add_midi_chunk(midi);
                     }
                 }
                 ids::RECORDING => {
-                    if let Some(midi) = self.read_chunk::<chunks::AudioRecordingChunk>(read_data, record_form_pos, &id) {
+                    if let Some(midi) = self.parse_known::<chunks::AudioRecordingChunk>(&id, offset)? {
                         form.set_recording(midi);
                     }
                 }
                 ids::APPLICATION => {
-                    if let Some(app) = self.read_chunk::<chunks::ApplicationSpecificChunk>(read_data, record_form_pos, &id) {
+                    if let Some(app) = self.parse_known::<chunks::ApplicationSpecificChunk>(&id, offset)? {
                         form.add_app_chunk(app);
                     }
                 }
                 ids::COMMENTS => {
-                    if let Some(comm) = self.read_chunk::<chunks::CommentsChunk>(read_data, record_form_pos, &id) {
+                    if let Some(comm) = self.parse_known::<chunks::CommentsChunk>(&id, offset)? {
                         form.set_comments(comm);
                     }
                 }
                 ids::NAME | ids::AUTHOR | ids::COPYRIGHT | ids::ANNOTATION => {
-                    if let Some(text) = self.read_chunk::<chunks::TextChunk>(read_data, record_form_pos, &id) {
+                    if let Some(text) = self.parse_known::<chunks::TextChunk>(&id, offset)? {
                         form.add_text_chunk(text);
                     }
                 }
-                ids::FVER => {
-                    unimplemented!("FVER chunk detected");
-                }
-                // 3 bytes "ID3" identifier
-                // TODO merge both options
-                // ID3 chunks aren't stored in the FORM chunk. should they
-                // be stored next to the form chunk in the reader?
-                [73, 68, 51, _] => {
-                    self.buf.seek(SeekFrom::Current(-4)).unwrap();
-
-                    match self.read_chunk::<chunks::ID3v2Chunk>(read_data, record_form_pos, &id) {
-                        // Ok(chunk) => self.id3v2_tags.push(chunk),
-                        Some(chunk) => self.id3v2_tag = Some(chunk.tag),
-                        None => {
-                            println!("Build ID3 chunk failed");
-                            self.buf.seek(SeekFrom::Current(3)).unwrap();
-                        },
-                        _ => ()
+                // ID3 tag wrapped in a regular chunk, as written by itunes
+                // and AiffWriter. the tag may be shorter than the chunk
+                ids::ID3_CHUNK => {
+                    self.buf.seek(SeekFrom::Current(4))?;
+                    if let Some(chunk) = self.parse_known::<chunks::ID3v2Chunk>(&id, offset)? {
+                        self.id3v2_tag = Some(chunk.tag);
+                        self.id3v2_version = Some(chunk.version);
                     }
                 }
-                [_, 73, 68, 51] => {
-                    self.buf.seek(SeekFrom::Current(-3)).unwrap();
-
-                    match self.read_chunk::<chunks::ID3v2Chunk>(read_data, record_form_pos, ids::ID3) {
-                        // Ok(chunk) => self.id3v2_tags.push(chunk),
-                        Some(chunk) => self.id3v2_tag = Some(chunk.tag),
-                        None => {
-                            println!("Build ID3 chunk failed");
-                            self.buf.seek(SeekFrom::Current(3)).unwrap();
-                        },
-                        _ => ()
+                // FVER, the apple chunks (CHAN, BASC, TRNS, CATE) and anything
+                // else unknown is kept as raw bytes so writing doesn't drop it
+                id => {
+                    self.report(Diagnostic::new(Severity::Info, offset, Some(*id), Issue::UnknownChunk))?;
+                    if self.options.chunk_load(id) != ChunkLoad::Skip {
+                        let tag_id = String::from_utf8_lossy(id).into_owned();
+                        self.form_buf_locations.entry(tag_id).or_insert(offset + 4);
+                    }
+                    if let Some(raw) = self.parse_known::<chunks::RawChunk>(id, offset)? {
+                        form.add_raw_chunk(raw);
                     }
-
-                }
-                [84, 65, 71, _] => println!("v1 id3"), // "TAG_"
-                [_, 84, 65, 71] => println!("v1 id3"), // "_TAG"
-                ids::CHAN | ids::BASC | ids::TRNS | ids::CATE => {
-                    unimplemented!("apple stuff detected")
                 }
-                id => println!(
-                    "other chunk {:?} {:?}",
-                    id,
-                    String::from_utf8_lossy(id)
-                ),
-                // _ => (),
             };
+
+            // nothing can follow data running to the end of the file
+            if unsized_sound || truncated {
+                chunks.advance(id, size);
+                break;
+            }
+            self.next_chunk(&mut chunks, id, size, file_len)?;
         }
-        self.form_chunk = Some(form);
+        self.read_trailing(chunks.pos(), file_len)?;
 
-        // FIXME handle remaining bytes
-        println!("buffer complete {} byte(s) left", self.buf.available());
-        // set position to end?
+        let frames = match (form.common().clone(), sound) {
+            (Some(common), Some((sound_offset, sound_size, truncated))) => self.check_frame_count(&common, sound_offset, sound_size, truncated)?,
+            _ => None,
+        };
+        if let (Some(frames), Some(common)) = (frames, form.common().clone()) {
+            form.set_common(chunks::CommonChunk { num_sample_frames: frames, ..common });
+        }
+        self.form_chunk = Some(form);
 
         Ok(())
     }
 
+    // the handler only sees the chunk body, wherever it stops reading the
+    // reader continues after the chunk
+    fn parse_custom_chunk(
+        &mut self,
+        id: ids::ChunkID,
+        parse: CustomChunkParser,
+        write: Option<CustomChunkWriter>,
+    ) -> Result<Option<CustomChunk>, chunks::ChunkError> {
+        let load = self.options.chunk_load(&id);
+        let start = self.buf.position() - 4;
+        if load != ChunkLoad::Skip {
+            let tag_id = String::from_utf8_lossy(&id).into_owned();
+            self.form_buf_locations.entry(tag_id).or_insert(start);
+        }
+
+        let size = read_u32_be(&mut self.buf)?;
+        let chunk_end = self.buf.position() + size as u64 + size as u64 % 2;
+
+        let chunk = if load == ChunkLoad::Full {
+            let mut body = (&mut self.buf).take(size as u64);
+            let value = parse(&mut body, size).map(|v| Some(CustomChunk::new(id, v, write)));
+            self.recover(value, start, id)?
+        } else {
+            None
+        };
+
+        self.buf.seek(SeekFrom::Start(chunk_end))?;
+        Ok(chunk)
+    }
+
+    pub fn register_chunk_handler(
+        &mut self,
+        id: ids::ChunkID,
+        parse: CustomChunkParser,
+        write: Option<CustomChunkWriter>,
+    ) {
+        self.custom_chunks.register(id, parse, write);
+    }
+
+    pub fn chunk_handlers_mut(&mut self) -> &mut CustomChunkRegistry {
+        &mut self.custom_chunks
+    }
+
+    // the value parsed by the handler registered for `id`
+    pub fn custom_chunk<T: 'static>(&self, id: &ids::ChunkID) -> Option<&T> {
+        self.form_chunk.as_ref().and_then(|f| f.custom_chunk::<T>(id))
+    }
+
     pub fn form(&self) -> &Option<FormChunk> {
         &self.form_chunk
     }
 
-    // TODO need to check available
+    pub fn form_mut(&mut self) -> &mut Option<FormChunk> {
+        &mut self.form_chunk
+    }
+
+    // writes the form and ID3 tag back out, the tag in the version it was
+    // read as. sound data is only written if it was loaded, so use
+    // read_all_form_data rather than parse before saving
+    pub fn write<W: Write>(&self, sink: W) -> io::Result<()> {
+        let form = match &self.form_chunk {
+            Some(f) => f,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "form chunk not parsed",
+                ))
+            }
+        };
+
+        AiffWriter::new(sink)
+            .id3_version(self.id3v2_version.unwrap_or(id3::Version::Id3v24))
+            .write_form(form, self.id3v2_tag.as_ref())
+    }
+
+    // the version of the ID3 tag in the file, None without one
+    pub fn id3v2_version(&self) -> Option<id3::Version> {
+        self.id3v2_version
+    }
+
+    // see the metadata module for the precedence between sources
+    pub fn metadata(&self) -> Metadata {
+        Metadata::from_sources(self.form_chunk.as_ref(), self.id3v2_tag.as_ref())
+    }
+
+    pub fn metadata_conflicts(&self) -> Vec<MetadataConflict> {
+        Metadata::conflicts(self.form_chunk.as_ref(), self.id3v2_tag.as_ref())
+    }
+
+    // writes `metadata` to both the native text chunks and the ID3 tag,
+    // creating the tag if the file didn't have one
+    pub fn set_metadata(
+        &mut self,
+        metadata: &Metadata,
+    ) -> Result<(), chunks::ChunkError> {
+        let form = match &mut self.form_chunk {
+            Some(f) => f,
+            None => return Err(chunks::ChunkError::InvalidData("form chunk not parsed")),
+        };
+        let tag = self.id3v2_tag.get_or_insert_with(id3::Tag::new);
+        metadata.write_to(form, tag);
+
+        Ok(())
+    }
+
+    pub fn artwork(&self) -> Vec<&Picture> {
+        match &self.id3v2_tag {
+            Some(tag) => artwork::pictures(tag),
+            None => vec![],
+        }
+    }
+
+    pub fn artwork_by_type(&self, picture_type: PictureType) -> Option<&Picture> {
+        artwork::picture_by_type(self.id3v2_tag.as_ref()?, picture_type)
+    }
+
+    pub fn front_cover(&self) -> Option<&Picture> {
+        artwork::front_cover(self.id3v2_tag.as_ref()?)
+    }
+
+    // replaces any artwork of the same picture type, creating the ID3 tag if
+    // the file didn't have one
+    pub fn set_artwork(&mut self, picture: Picture) {
+        let tag = self.id3v2_tag.get_or_insert_with(id3::Tag::new);
+        artwork::set_picture(tag, picture);
+    }
+
+    pub fn remove_artwork(&mut self, picture_type: PictureType) {
+        if let Some(tag) = &mut self.id3v2_tag {
+            artwork::remove_picture(tag, picture_type);
+        }
+    }
+
+    pub fn app_registry(&self) -> &ApplicationRegistry {
+        &self.app_registry
+    }
+
+    pub fn app_registry_mut(&mut self) -> &mut ApplicationRegistry {
+        &mut self.app_registry
+    }
+
+    pub fn register_app_decoder(
+        &mut self,
+        signature: ids::ChunkID,
+        decoder: ApplicationDecoder,
+    ) {
+        self.app_registry.register(signature, decoder);
+    }
+
+    // decodes every APPL chunk with a registered decoder, in file order.
+    // chunks without one are skipped, they're still in `form().app_chunks()`
+    pub fn app_data(
+        &self,
+    ) -> Vec<Result<Box<dyn ApplicationData>, chunks::ChunkError>> {
+        self.form_chunk
+            .iter()
+            .filter_map(|f| f.app_chunks().as_ref())
+            .flatten()
+            .filter_map(|a| self.app_registry.decode(a))
+            .collect()
+    }
+
+    // see FormChunk::set_app_data. the form has to be read first
+    pub fn set_app_data(&mut self, data: &dyn ApplicationData) -> Result<(), chunks::ChunkError> {
+        match &mut self.form_chunk {
+            Some(form) => form.set_app_data(data),
+            None => return Err(chunks::ChunkError::InvalidData("form chunk not parsed")),
+        }
+
+        Ok(())
+    }
+
     // TODO return result iterator or complete buffer of data
     // TODO pack frams
     // should return a generic AiffSample<u8/u16/u32> etc
@@ -215,15 +705,13 @@ impl<Source: Read + Seek> AiffReader<Source> {
         // playback occurs at <sample_rate> frames per second
         // num samples is always > 0 so shouldn't be any conversion issues
         // maybe it should be stored as a u16?
-        let sample_points =
-            (c.num_sample_frames * c.num_channels as u32) as usize;
-        println!("sample points {:?}", sample_points);
+        let bytes_per_point = walk::point_size(c.bit_rate);
+        // no more than the sound data holds, whatever COMM says
+        let sample_points = (c.num_sample_frames as usize)
+            .saturating_mul(c.num_channels.max(0) as usize)
+            .min(s.sound_data.len() / bytes_per_point.max(1));
 
         let mut samples = Vec::with_capacity(sample_points);
-        let mut bytes_per_point = (c.bit_rate / 8) as usize;
-        if c.bit_rate % 8 != 0 {
-            bytes_per_point += 1;
-        }
 
         for point in 0..sample_points {
             samples.push(T::parse(&s.sound_data, point * bytes_per_point, c.bit_rate));
@@ -233,79 +721,177 @@ impl<Source: Read + Seek> AiffReader<Source> {
     }
 
     // TODO create samples iterator for better performance
+
+    // plays through the INST sustain / release loops, see LoopingSamples.
+    // without an INST chunk this is plain playback
+    pub fn looping_samples<T: SampleType>(&self) -> Result<LoopingSamples<'_, T>, chunks::ChunkError> {
+        let f = match &self.form_chunk {
+            Some(f) => f,
+            None => return Err(chunks::ChunkError::InvalidData("form chunk not parsed")),
+        };
+        let (s, c) = match (f.sound(), f.common()) {
+            (Some(s), Some(c)) => (s, c),
+            _ => return Err(chunks::ChunkError::InvalidData("sound data not loaded")),
+        };
+        let loops = f.instrument_loops().transpose()?.unwrap_or_default();
+
+        Ok(LoopingSamples::new(s, c, loops))
+    }
 }
 
 // enums are always the max possible size, so neeeds to be structs and traits
 
-// TODO remove panics
 // TODO move these into their own file - what's a good name?
 
-pub fn read_chunk_id(r: &mut impl Read) -> ids::ChunkID {
+pub fn read_chunk_id(r: &mut impl Read) -> io::Result<ids::ChunkID> {
     let mut id = [0; 4];
-    if let Err(e) = r.read_exact(&mut id) {
-        panic!("unable to read_u8 {:?}", e)
-    }
-    id
+    r.read_exact(&mut id)?;
+    Ok(id)
 }
 
-pub fn read_u8(r: &mut impl Read) -> u8 {
+pub fn read_u8(r: &mut impl Read) -> io::Result<u8> {
     let mut b = [0; 1];
-    if let Err(e) = r.read_exact(&mut b) {
-        panic!("unable to read_u8 {:?}", e)
-    }
-    b[0]
+    r.read_exact(&mut b)?;
+    Ok(b[0])
 }
 
-pub fn read_u16_be(r: &mut impl Read) -> u16 {
+pub fn read_u16_be(r: &mut impl Read) -> io::Result<u16> {
     let mut b = [0; 2];
-    if let Err(e) = r.read_exact(&mut b) {
-        panic!("unable to read_u8 {:?}", e)
-    }
-    u16::from_be_bytes(b)
+    r.read_exact(&mut b)?;
+    Ok(u16::from_be_bytes(b))
 }
 
-pub fn read_u32_be(r: &mut impl Read) -> u32 {
+pub fn read_u32_be(r: &mut impl Read) -> io::Result<u32> {
     let mut b = [0; 4];
-    if let Err(e) = r.read_exact(&mut b) {
-        panic!("unable to read_i32_be {:?}", e)
-    }
-    u32::from_be_bytes(b)
+    r.read_exact(&mut b)?;
+    Ok(u32::from_be_bytes(b))
 }
 
-pub fn read_i8_be(r: &mut impl Read) -> i8 {
+pub fn read_i8_be(r: &mut impl Read) -> io::Result<i8> {
     let mut b = [0; 1];
-    if let Err(e) = r.read_exact(&mut b) {
-        panic!("unable to read_i32_be {:?}", e)
-    }
-    i8::from_be_bytes(b)
+    r.read_exact(&mut b)?;
+    Ok(i8::from_be_bytes(b))
 }
 
-pub fn read_i16_be(r: &mut impl Read) -> i16 {
+pub fn read_i16_be(r: &mut impl Read) -> io::Result<i16> {
     let mut b = [0; 2];
-    if let Err(e) = r.read_exact(&mut b) {
-        panic!("unable to read_i32_be {:?}", e)
-    }
-    i16::from_be_bytes(b)
+    r.read_exact(&mut b)?;
+    Ok(i16::from_be_bytes(b))
 }
 
-pub fn read_i32_be(r: &mut impl Read) -> i32 {
+pub fn read_i32_be(r: &mut impl Read) -> io::Result<i32> {
     let mut b = [0; 4];
-    if let Err(e) = r.read_exact(&mut b) {
-        panic!("unable to read_i32_be {:?}", e)
-    }
-    i32::from_be_bytes(b)
+    r.read_exact(&mut b)?;
+    Ok(i32::from_be_bytes(b))
 }
 
-// TODO testme with pascal strings
-pub fn read_pstring<R: Read + Seek>(r: &mut R) -> String {
-    let len = read_u8(r);
+// a count byte, then the text. the count byte plus the text is padded to an
+// even length, so there's a pad byte when the text length is even
+pub fn read_pstring<R: Read + Seek>(
+    r: &mut R,
+    decoding: TextDecoding,
+) -> Result<String, chunks::ChunkError> {
+    let len = read_u8(r)?;
     let mut str_buf = vec![0; len as usize];
-    r.read_exact(&mut str_buf).unwrap();
+    r.read_exact(&mut str_buf)?;
+
+    if (1 + len as usize) % 2 == 1 {
+        r.seek(SeekFrom::Current(1))?;
+    }
+
+    macroman::decode(&str_buf, decoding)
+}
+
+// printable ASCII, as every chunk id should be
+pub(crate) fn is_plausible_id(id: &[u8; 4]) -> bool {
+    id.iter().all(|b| (0x20..=0x7E).contains(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
 
-    if len % 2 > 0 {
-        // skip pad byte if odd
-        r.seek(SeekFrom::Current(1)).unwrap();
+    fn pstring(bytes: &[u8], decoding: TextDecoding) -> (Result<String, chunks::ChunkError>, u64) {
+        let mut r = Cursor::new(bytes);
+        let text = read_pstring(&mut r, decoding);
+        (text, r.position())
     }
 
-    String::from_utf8(str_buf).unwrap()
+    #[test]
+    fn pstring_padding() {
+        // the count byte and text together are padded to an even length
+        assert_eq!(pstring(b"\x03abcX", TextDecoding::Strict).0.unwrap(), "abc");
+        assert_eq!(pstring(b"\x03abcX", TextDecoding::Strict).1, 4);
+        assert_eq!(pstring(b"\x02ab\0X", TextDecoding::Strict).1, 4);
+        assert_eq!(pstring(b"\x00\0X", TextDecoding::Strict).1, 2);
+    }
+
+    #[test]
+    fn pstring_macroman() {
+        let (text, pos) = pstring(b"\x05D\x8Ebut", TextDecoding::Strict);
+        assert_eq!(text.unwrap(), "Début");
+        assert_eq!(pos, 6);
+
+        let (text, _) = pstring(b"\x02a\x01\0", TextDecoding::Lossy);
+        assert_eq!(text.unwrap(), "a\u{FFFD}");
+        let (text, _) = pstring(b"\x02a\x01\0", TextDecoding::Strict);
+        assert!(matches!(text, Err(chunks::ChunkError::InvalidTextByte(1))));
+    }
+
+    // 2 channels of 16 bit, 4 frames
+    fn short_file() -> Vec<u8> {
+        let mut bytes = b"FORM\0\0\0\x3EAIFF".to_vec();
+        bytes.extend_from_slice(b"COMM\0\0\0\x12\0\x02\0\0\0\x04\0\x10");
+        bytes.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"SSND\0\0\0\x18\0\0\0\0\0\0\0\0");
+        bytes.extend(0..16);
+        bytes
+    }
+
+    #[test]
+    fn missing_pad_byte_and_trailing_tag() {
+        // a NAME without its pad byte between COMM and SSND, then an ID3v1
+        // tag after the FORM
+        let mut bytes = short_file();
+        bytes.splice(38..38, b"NAME\0\0\0\x03abc".iter().copied());
+        bytes[7] += 11;
+        bytes.extend_from_slice(b"TAG");
+        bytes.extend_from_slice(&[0; 125]);
+
+        let mut reader = AiffReader::new(Cursor::new(bytes));
+        reader.read_all_form_data().unwrap();
+        let form = reader.form().as_ref().unwrap();
+        assert_eq!(form.sound().as_ref().unwrap().sound_data.len(), 16);
+        assert_eq!(form.text(chunks::TextChunkType::Name), Some("abc"));
+
+        let issues: Vec<_> = reader.diagnostics().iter().map(|d| (d.offset, d.issue.clone())).collect();
+        assert_eq!(issues, vec![(38, Issue::MissingPadByte), (81, Issue::ID3v1Tag)]);
+    }
+
+    #[test]
+    fn truncated_file() {
+        // cut off part way through the third frame
+        let mut bytes = short_file();
+        bytes.truncate(bytes.len() - 6);
+
+        let mut reader = AiffReader::new(Cursor::new(bytes.clone()));
+        reader.read_all_form_data().unwrap();
+        assert!(reader.form().as_ref().unwrap().sound().is_none());
+        assert_eq!(reader.diagnostics().last().unwrap().severity, Severity::Error);
+
+        let options = ReaderOptions::new().recovery(true);
+        let mut reader = AiffReader::with_options(Cursor::new(bytes), options);
+        reader.read_all_form_data().unwrap();
+        let form = reader.form().as_ref().unwrap();
+        assert_eq!(form.sound().as_ref().unwrap().sound_data, (0..10).collect::<Vec<u8>>());
+        assert_eq!(form.common().as_ref().unwrap().num_sample_frames, 2);
+
+        let issues: Vec<_> = reader.diagnostics().iter().map(|d| (d.severity, d.issue.clone())).collect();
+        assert_eq!(issues, vec![
+            (Severity::Warning, Issue::FormSizeMismatch { declared: 0x3E, actual: 0x38 }),
+            (Severity::Warning, Issue::TruncatedChunk { declared: 0x18, available: 0x12 }),
+            (Severity::Warning, Issue::FrameCountMismatch { declared: 4, actual: 2 }),
+        ]);
+    }
 }
@@ -0,0 +1,242 @@
+// fixes the header of a file a recorder never finished. those are left with
+// FORM and SSND sizes of 0 or 0xFFFFFFFF and a frame count of 0, though the
+// sound data is all there. files cut short after the header was written have
+// the opposite problem, sizes and a frame count past the end of the data.
+// either way the sizes are worked out from the file length and the frame
+// count from the sound data, then written over the old values.
+//
+//     let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+//     let repair = aiff::repair(&mut file)?;
+//
+// `plan_repair` works out the same values without writing anything. to read
+// such a file as it is, use ReaderOptions::recovery instead
+use super::{
+    chunks::{ChunkError, CommonChunk},
+    detect, ids, probe, stream,
+    walk::{self, is_unsized, ChunkWalk},
+};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// the values written, None for those that were fine
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Repair {
+    pub form_size: Option<u32>,
+    pub sound_size: Option<u32>,
+    pub sample_frames: Option<u32>,
+    // an odd sized SSND at the end of the file gets its pad byte
+    pub pad_byte: bool,
+}
+
+impl Repair {
+    pub fn is_needed(&self) -> bool {
+        self.form_size.is_some()
+            || self.sound_size.is_some()
+            || self.sample_frames.is_some()
+            || self.pad_byte
+    }
+}
+
+// where each value goes
+struct Plan {
+    repair: Repair,
+    sound_pos: u64,
+    frames_pos: u64,
+}
+
+fn plan<R: Read + Seek>(r: &mut R) -> Result<Plan, ChunkError> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+
+    let mut header = [0; detect::HEADER_SIZE];
+    r.read_exact(&mut header)?;
+    let (id, form_size) = walk::decode_header(&header);
+    if detect::detect(&header).is_none() {
+        return Err(ChunkError::InvalidID(id));
+    }
+    let form_end = walk::form_end(form_size, file_len);
+
+    let mut common: Option<(u64, CommonChunk, bool)> = None;
+    let mut sound: Option<(u64, u32, u32)> = None; // offset, size, data offset
+    let mut chunks = ChunkWalk::new(form_end);
+    while let Some(pos) = chunks.next_header() {
+        r.seek(SeekFrom::Start(pos))?;
+        let (id, size) = match walk::read_header(r)? {
+            Some(h) => h,
+            None => break,
+        };
+        chunks.advance(id, size);
+
+        match &id {
+            ids::COMMON if common.is_none() => {
+                let mut body = vec![];
+                r.by_ref()
+                    .take(size.min(walk::COMM_PROBE_LEN) as u64)
+                    .read_to_end(&mut body)?;
+                let uncompressed = probe::decode_compression(&body)
+                    .as_ref()
+                    .map_or(true, stream::is_uncompressed);
+                let c = CommonChunk::decode(size as i32, &body)?;
+                common = Some((pos, c, uncompressed));
+            }
+            ids::SOUND if sound.is_none() => {
+                let mut offset = [0; 4];
+                r.read_exact(&mut offset)?;
+                sound = Some((pos, size, u32::from_be_bytes(offset)));
+            }
+            _ => (),
+        }
+    }
+
+    let (sound_pos, declared, data_offset) =
+        sound.ok_or(ChunkError::InvalidData("missing SSND chunk"))?;
+    let too_large = ChunkError::InvalidData("sound data too large for AIFF");
+
+    // a FORM or SSND running past the end of the file was cut short, and is
+    // sized from the file like an unsized one
+    let form_cut = is_unsized(form_size) || form_size as u64 > file_len - 8;
+    let sound_cut =
+        !is_unsized(declared) && sound_pos + 8 + declared as u64 > file_len;
+
+    let mut repair = Repair::default();
+    let sound_size = if is_unsized(declared) || sound_cut {
+        let size = form_end - sound_pos - 8;
+        if size >= u32::MAX as u64 {
+            return Err(too_large);
+        }
+        repair.sound_size = Some(size as u32);
+        // only added when the FORM size is being worked out too, otherwise
+        // it would end up outside the FORM
+        repair.pad_byte = size % 2 == 1 && form_cut;
+        size as u32
+    } else {
+        declared
+    };
+
+    if form_cut {
+        let size = file_len - 8 + repair.pad_byte as u64;
+        if size >= u32::MAX as u64 {
+            return Err(too_large);
+        }
+        repair.form_size = Some(size as u32);
+    }
+
+    let mut frames_pos = 0;
+    if let Some((comm_pos, c, uncompressed)) = common {
+        let frame_size = walk::frame_size(&c) as u64;
+        // compressed frames can't be counted from the data size
+        if frame_size > 0 && uncompressed {
+            let data =
+                (sound_size as u64).saturating_sub(8 + data_offset as u64);
+            let frames = (data / frame_size).min(u32::MAX as u64) as u32;
+            let missing = c.num_sample_frames == 0 && frames > 0;
+            if missing || (sound_cut && frames < c.num_sample_frames) {
+                repair.sample_frames = Some(frames);
+            }
+        }
+        frames_pos = comm_pos + 8 + 2;
+    }
+
+    Ok(Plan {
+        repair,
+        sound_pos,
+        frames_pos,
+    })
+}
+
+// the values `repair` would write, without touching the file
+pub fn plan_repair<R: Read + Seek>(r: &mut R) -> Result<Repair, ChunkError> {
+    plan(r).map(|p| p.repair)
+}
+
+// rewrites the header sizes and frame count in place, leaving everything
+// else as it was. a well formed file isn't written to
+pub fn repair<F: Read + Write + Seek>(f: &mut F) -> Result<Repair, ChunkError> {
+    let Plan {
+        repair,
+        sound_pos,
+        frames_pos,
+    } = plan(f)?;
+
+    for (pos, value) in &[
+        (4, repair.form_size),
+        (sound_pos + 4, repair.sound_size),
+        (frames_pos, repair.sample_frames),
+    ] {
+        if let Some(value) = value {
+            f.seek(SeekFrom::Start(*pos))?;
+            f.write_all(&value.to_be_bytes())?;
+        }
+    }
+    if repair.pad_byte {
+        f.seek(SeekFrom::End(0))?;
+        f.write_all(&[0])?;
+    }
+    f.flush()?;
+
+    Ok(repair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // 2 channels of 16 bit, 4 frames
+    fn file() -> Vec<u8> {
+        let mut bytes = b"FORM\0\0\0\x3EAIFF".to_vec();
+        bytes.extend_from_slice(b"COMM\0\0\0\x12\0\x02\0\0\0\x04\0\x10");
+        bytes.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"SSND\0\0\0\x18\0\0\0\0\0\0\0\0");
+        bytes.extend(0..16);
+        bytes
+    }
+
+    #[test]
+    fn well_formed() {
+        let mut bytes = Cursor::new(file());
+        assert!(!repair(&mut bytes).unwrap().is_needed());
+        assert_eq!(bytes.into_inner(), file());
+    }
+
+    #[test]
+    fn unsized_sizes() {
+        let mut bytes = file();
+        bytes[4..8].copy_from_slice(&[0; 4]);
+        bytes[42..46].copy_from_slice(&[0xFF; 4]);
+        bytes[22..26].copy_from_slice(&[0; 4]);
+
+        let mut bytes = Cursor::new(bytes);
+        let repair = repair(&mut bytes).unwrap();
+        assert_eq!(repair.form_size, Some(0x3E));
+        assert_eq!(repair.sound_size, Some(0x18));
+        assert_eq!(repair.sample_frames, Some(4));
+        assert_eq!(bytes.into_inner(), file());
+    }
+
+    #[test]
+    fn truncated() {
+        // cut off part way through the third frame, the odd sized SSND left
+        // gets a pad byte
+        let mut bytes = file();
+        bytes.truncate(bytes.len() - 5);
+
+        let mut bytes = Cursor::new(bytes);
+        let repair = repair(&mut bytes).unwrap();
+        assert_eq!(
+            repair,
+            Repair {
+                form_size: Some(0x3A),
+                sound_size: Some(0x13),
+                sample_frames: Some(2),
+                pad_byte: true,
+            }
+        );
+
+        let bytes = bytes.into_inner();
+        assert_eq!(bytes.len(), 0x42);
+        assert_eq!(bytes[4..8], [0, 0, 0, 0x3A]);
+        assert_eq!(bytes[22..26], [0, 0, 0, 2]);
+        assert_eq!(bytes[42..46], [0, 0, 0, 0x13]);
+        assert!(!plan_repair(&mut Cursor::new(bytes)).unwrap().is_needed());
+    }
+}
@@ -1,3 +1,9 @@
+use super::chunks::{
+    CommonChunk, InstrumentLoops, LoopPoints, PlayMode, SoundDataChunk,
+};
+use super::walk;
+use std::{io, marker::PhantomData};
+
 pub struct AiffSamples {
     pos: usize,
 }
@@ -57,6 +63,51 @@ impl SampleType for i32 {
     }
 }
 
+// the other direction, for writing sound data. sample points are written
+// big endian at the bit width of the file, which has to fit the type
+pub trait EncodeSample {
+    fn encode(&self, bit_width: i16, out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+fn unsupported_width(bit_width: i16) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("can't encode sample at bit width {}", bit_width),
+    )
+}
+
+impl EncodeSample for i8 {
+    fn encode(&self, bit_width: i16, out: &mut Vec<u8>) -> io::Result<()> {
+        match bit_width {
+            8 => out.extend_from_slice(&self.to_be_bytes()),
+            b => return Err(unsupported_width(b)),
+        }
+        Ok(())
+    }
+}
+
+impl EncodeSample for i16 {
+    fn encode(&self, bit_width: i16, out: &mut Vec<u8>) -> io::Result<()> {
+        match bit_width {
+            16 => out.extend_from_slice(&self.to_be_bytes()),
+            b => return Err(unsupported_width(b)),
+        }
+        Ok(())
+    }
+}
+
+impl EncodeSample for i32 {
+    fn encode(&self, bit_width: i16, out: &mut Vec<u8>) -> io::Result<()> {
+        match bit_width {
+            32 => out.extend_from_slice(&self.to_be_bytes()),
+            // the low 3 bytes, as parse reads them
+            24 => out.extend_from_slice(&self.to_be_bytes()[1..]),
+            b => return Err(unsupported_width(b)),
+        }
+        Ok(())
+    }
+}
+
 // impl SampleType for f32 {
 //     fn parse(data: &[u8], pos: usize, bit_width: i16) -> Self {
 //         let int_val = i32::parse(data, pos, bit_width);
@@ -148,3 +199,269 @@ impl Iterator for Samples32 {
         ]))
     }
 }
+
+// sampler style playback. plays up to the sustain loop and repeats it, forward
+// or ping-pong depending on its play mode, until note_off is called. playback
+// then carries on from wherever it is, through the release loop and on to
+// the end of the sound data.
+//
+// the release loop is played straight through unless release_repeats is set,
+// so a released note always ends. yields interleaved sample points, like
+// AiffReader::samples
+pub struct LoopingSamples<'a, T: SampleType> {
+    sound_data: &'a [u8],
+    bit_rate: i16,
+    bytes_per_point: usize,
+    num_channels: usize,
+    num_sample_frames: u32,
+    loops: InstrumentLoops,
+    frame: u32,
+    channel: usize,
+    reverse: bool,
+    released: bool,
+    release_repeats: u32,
+    sample_type: PhantomData<T>,
+}
+
+impl<'a, T: SampleType> LoopingSamples<'a, T> {
+    pub fn new(
+        sound: &'a SoundDataChunk,
+        common: &CommonChunk,
+        loops: InstrumentLoops,
+    ) -> LoopingSamples<'a, T> {
+        LoopingSamples {
+            sound_data: &sound.sound_data,
+            bit_rate: common.bit_rate,
+            bytes_per_point: walk::point_size(common.bit_rate),
+            num_channels: common.num_channels.max(1) as usize,
+            num_sample_frames: common.num_sample_frames,
+            loops,
+            frame: 0,
+            channel: 0,
+            reverse: false,
+            released: false,
+            release_repeats: 0,
+            sample_type: PhantomData,
+        }
+    }
+
+    // how many extra times the release loop plays before playback continues
+    // to the end
+    pub fn release_repeats(mut self, repeats: u32) -> Self {
+        self.release_repeats = repeats;
+        self
+    }
+
+    pub fn note_off(&mut self) {
+        self.released = true;
+        self.reverse = false;
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    // the frame the next sample point belongs to
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    fn active_loop(&self) -> Option<LoopPoints> {
+        match self.released {
+            false => self.loops.sustain,
+            true if self.release_repeats > 0 => self.loops.release,
+            true => None,
+        }
+    }
+
+    fn advance_frame(&mut self) {
+        let active = match self.active_loop() {
+            Some(l) if l.play_mode.is_looping() => l,
+            _ => {
+                self.reverse = false;
+                self.frame += 1;
+                return;
+            }
+        };
+
+        // a repeat of a ping-pong loop ends back at the start, so it's
+        // counted there
+        if self.reverse {
+            if self.frame <= active.start {
+                // bounce without repeating the first frame
+                self.reverse = false;
+                self.frame = (active.start + 1).min(active.end - 1);
+                if self.released {
+                    self.release_repeats -= 1;
+                }
+            } else {
+                self.frame -= 1;
+            }
+        } else if self.frame + 1 == active.end {
+            match active.play_mode {
+                PlayMode::ForwardBackward if active.end - active.start > 1 => {
+                    self.reverse = true;
+                    self.frame = active.end - 2;
+                }
+                _ => {
+                    self.frame = active.start;
+                    if self.released {
+                        self.release_repeats -= 1;
+                    }
+                }
+            }
+        } else {
+            self.frame += 1;
+        }
+    }
+}
+
+impl<'a, T: SampleType> Iterator for LoopingSamples<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame >= self.num_sample_frames {
+            return None;
+        }
+
+        let point = self.frame as usize * self.num_channels + self.channel;
+        let pos = point * self.bytes_per_point;
+        if pos + self.bytes_per_point > self.sound_data.len() {
+            return None;
+        }
+
+        let sample = T::parse(self.sound_data, pos, self.bit_rate);
+
+        self.channel += 1;
+        if self.channel == self.num_channels {
+            self.channel = 0;
+            self.advance_frame();
+        }
+
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ids, reader::AiffReader};
+    use std::io::Cursor;
+
+    const FRAMES: u8 = 10;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    // mono 8 bit, each frame holding its own number. the loops are (play
+    // mode, start, end) in frames, with a marker for each point
+    fn reader(sustain: (i16, u32, u32), release: (i16, u32, u32)) -> AiffReader<Cursor<Vec<u8>>> {
+        let mut comm = vec![0, 1, 0, 0, 0, FRAMES, 0, 8];
+        comm.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+
+        let mut mark = vec![0, 4];
+        for (id, position) in [sustain.1, sustain.2, release.1, release.2].iter().enumerate() {
+            mark.extend_from_slice(&(id as i16 + 1).to_be_bytes());
+            mark.extend_from_slice(&position.to_be_bytes());
+            mark.extend_from_slice(&[0, 0]);
+        }
+
+        let mut inst = vec![60, 0, 0, 127, 1, 127, 0, 0];
+        for (l, (mode, ..)) in [sustain, release].iter().enumerate() {
+            inst.extend_from_slice(&mode.to_be_bytes());
+            inst.extend_from_slice(&(l as i16 * 2 + 1).to_be_bytes());
+            inst.extend_from_slice(&(l as i16 * 2 + 2).to_be_bytes());
+        }
+
+        let mut ssnd = vec![0; 8];
+        ssnd.extend(0..FRAMES);
+
+        let mut form = b"AIFF".to_vec();
+        form.extend(chunk(ids::COMMON, &comm));
+        form.extend(chunk(ids::MARKER, &mark));
+        form.extend(chunk(ids::INSTRUMENT, &inst));
+        form.extend(chunk(ids::SOUND, &ssnd));
+        let mut bytes = ids::FORM.to_vec();
+        bytes.extend_from_slice(&(form.len() as u32).to_be_bytes());
+        bytes.extend(form);
+
+        let mut reader = AiffReader::new(Cursor::new(bytes));
+        reader.read_all_form_data().unwrap();
+        reader
+    }
+
+    const NONE: (i16, u32, u32) = (0, 0, 1);
+
+    // up to `len` frames, with note_off called before frame `off`
+    fn play(reader: &AiffReader<Cursor<Vec<u8>>>, repeats: u32, len: usize, off: Option<usize>) -> Vec<i8> {
+        let mut samples = reader.looping_samples::<i8>().unwrap().release_repeats(repeats);
+        let mut frames = vec![];
+        for i in 0..len {
+            if off == Some(i) {
+                samples.note_off();
+            }
+            match samples.next() {
+                Some(s) => frames.push(s),
+                None => break,
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn play_modes() {
+        let straight: Vec<i8> = (0..FRAMES as i8).collect();
+        // no loop, or a play mode not in the spec, plays straight through
+        assert_eq!(play(&reader((0, 2, 5), NONE), 0, 20, None), straight);
+        assert_eq!(play(&reader((5, 2, 5), NONE), 0, 20, None), straight);
+
+        assert_eq!(play(&reader((1, 2, 5), NONE), 0, 12, None), [0, 1, 2, 3, 4, 2, 3, 4, 2, 3, 4, 2]);
+        // the ends aren't played twice when it turns around
+        assert_eq!(play(&reader((2, 2, 5), NONE), 0, 12, None), [0, 1, 2, 3, 4, 3, 2, 3, 4, 3, 2, 3]);
+    }
+
+    #[test]
+    fn one_frame_loops() {
+        assert_eq!(play(&reader((1, 3, 4), NONE), 0, 8, None), [0, 1, 2, 3, 3, 3, 3, 3]);
+        assert_eq!(play(&reader((2, 3, 4), NONE), 0, 8, None), [0, 1, 2, 3, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn note_off() {
+        // carries on from where the sustain loop was to the end
+        let frames = play(&reader((1, 2, 5), NONE), 0, 20, Some(7));
+        assert_eq!(frames, [0, 1, 2, 3, 4, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        // forwards again from part way through a ping-pong
+        let frames = play(&reader((2, 2, 5), NONE), 0, 20, Some(6));
+        assert_eq!(frames, [0, 1, 2, 3, 4, 3, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        let reader = reader((1, 2, 5), NONE);
+        let mut samples = reader.looping_samples::<i8>().unwrap();
+        assert!(!samples.is_released());
+        samples.by_ref().take(4).for_each(drop);
+        samples.note_off();
+        assert!(samples.is_released());
+        assert_eq!(samples.frame(), 4);
+        assert_eq!(samples.by_ref().count(), 6);
+        assert_eq!(samples.next(), None);
+    }
+
+    #[test]
+    fn release_loop() {
+        // played straight through unless it's given repeats
+        let file = reader((1, 2, 5), (1, 6, 8));
+        let frames = play(&file, 0, 20, Some(3));
+        assert_eq!(frames, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let frames = play(&file, 2, 20, Some(3));
+        assert_eq!(frames, [0, 1, 2, 3, 4, 5, 6, 7, 6, 7, 6, 7, 8, 9]);
+
+        // a ping-pong repeat is there and back
+        let file = reader((1, 2, 5), (2, 6, 9));
+        let frames = play(&file, 1, 20, Some(3));
+        assert_eq!(frames, [0, 1, 2, 3, 4, 5, 6, 7, 8, 7, 6, 7, 8, 9]);
+    }
+}
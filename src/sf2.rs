@@ -0,0 +1,413 @@
+// SoundFont 2 export, the other common format for multisampled instruments.
+// the AIFFs become one instrument with a zone per file, wrapped in a single
+// preset. zones are mapped the same way as SFZ regions (see the sfz module),
+// from the same INST / MARK data:
+//
+//   lokey / hikey, lovel / hivel   keyRange / velRange
+//   pitch_keycenter                overridingRootKey
+//   tune                           fineTune
+//   volume                         initialAttenuation, cuts only
+//   sustain loop                   sampleModes 3 (loop while held, then play
+//                                  on) with the loop points in the sample
+//
+// SoundFont has no ping-pong loops, so alternate loops play forwards. sample
+// data is stored as 16 bit, deeper samples lose their low bits. stereo files
+// become a linked left / right pair of samples
+use super::{
+    chunks::FormChunk,
+    probe::Compression,
+    reader::AiffReader,
+    sfz::{SfzError, SfzLoopMode, SfzRegion},
+    walk,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// generator operators from section 8.1.2 of the spec
+const GEN_PAN: u16 = 17;
+const GEN_INSTRUMENT: u16 = 41;
+const GEN_KEY_RANGE: u16 = 43;
+const GEN_VEL_RANGE: u16 = 44;
+const GEN_INITIAL_ATTENUATION: u16 = 48;
+const GEN_FINE_TUNE: u16 = 52;
+const GEN_SAMPLE_ID: u16 = 53;
+const GEN_SAMPLE_MODES: u16 = 54;
+const GEN_OVERRIDING_ROOT_KEY: u16 = 58;
+
+const SAMPLE_MONO: u16 = 1;
+const SAMPLE_RIGHT: u16 = 2;
+const SAMPLE_LEFT: u16 = 4;
+
+// players read past the end of a sample while interpolating, so the spec
+// asks for at least 46 zero points after each one
+const SAMPLE_GAP: usize = 46;
+
+// one channel of one file, as it goes into the shdr chunk
+struct Sample {
+    name: String,
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    root_key: u8,
+    link: u16,
+    sample_type: u16,
+}
+
+#[derive(Default)]
+struct SoundFont {
+    data: Vec<i16>,
+    samples: Vec<Sample>,
+    zones: Vec<Vec<(u16, [u8; 2])>>, // generators, sampleID last
+}
+
+impl SoundFont {
+    fn add_region(
+        &mut self,
+        name: &str,
+        region: &SfzRegion,
+        form: &FormChunk,
+        path: &Path,
+    ) -> Result<(), SfzError> {
+        let unsupported = |msg| SfzError::Unsupported(path.to_owned(), msg);
+        let little_endian =
+            integer_byte_order(form.compression()).ok_or_else(|| {
+                unsupported("only integer samples can be exported")
+            })?;
+        let (common, sound) = match (form.common(), form.sound()) {
+            (Some(c), Some(s)) => (c, s),
+            _ => return Err(unsupported("no sound data")),
+        };
+        let channels = common.num_channels.max(0) as usize;
+        if channels == 0 || channels > 2 {
+            return Err(unsupported("only mono and stereo can be exported"));
+        }
+        if !(1..=32).contains(&common.bit_rate) {
+            return Err(unsupported("invalid sample size"));
+        }
+
+        let point_size = walk::point_size(common.bit_rate);
+        let data = sound
+            .sound_data
+            .get(sound.offset as usize..)
+            .unwrap_or_default();
+        let frames = (common.num_sample_frames as usize)
+            .min(data.len() / (point_size * channels));
+
+        let looping = region.loop_mode == Some(SfzLoopMode::LoopSustain);
+        let (loop_start, loop_end) = match (region.loop_start, region.loop_end)
+        {
+            (Some(start), Some(end)) if looping => {
+                (start, end.saturating_add(1))
+            }
+            _ => (0, 0),
+        };
+
+        let first = self.samples.len();
+        for channel in 0..channels {
+            let start = self.data.len();
+            self.data.extend((0..frames).map(|frame| {
+                let pos = (frame * channels + channel) * point_size;
+                to_i16(&data[pos..pos + point_size], little_endian)
+            }));
+            self.data.extend([0; SAMPLE_GAP].iter());
+
+            let (sample_type, link, suffix) = match (channels, channel) {
+                (1, _) => (SAMPLE_MONO, 0, ""),
+                (_, 0) => (SAMPLE_LEFT, first as u16 + 1, "L"),
+                _ => (SAMPLE_RIGHT, first as u16, "R"),
+            };
+            let offset = |frame: u32| start as u32 + frame.min(frames as u32);
+            self.samples.push(Sample {
+                name: format!("{}{}", name, suffix),
+                start: start as u32,
+                end: offset(frames as u32),
+                loop_start: offset(loop_start),
+                loop_end: offset(loop_end),
+                sample_rate: common.sample_rate.round() as u32,
+                root_key: region.pitch_keycenter.unwrap_or(60),
+                link,
+                sample_type,
+            });
+
+            let mut gens = vec![
+                (
+                    GEN_KEY_RANGE,
+                    [region.lokey.unwrap_or(0), region.hikey.unwrap_or(127)],
+                ),
+                (
+                    GEN_VEL_RANGE,
+                    [region.lovel.unwrap_or(0), region.hivel.unwrap_or(127)],
+                ),
+            ];
+            if let Some(key) = region.pitch_keycenter {
+                gens.push((
+                    GEN_OVERRIDING_ROOT_KEY,
+                    (key as i16).to_le_bytes(),
+                ));
+            }
+            if let Some(cents) = region.tune {
+                gens.push((GEN_FINE_TUNE, (cents as i16).to_le_bytes()));
+            }
+            // in centibels, and attenuation can't boost
+            let attenuation = (-region.volume.unwrap_or(0.) * 10.).round();
+            if attenuation > 0. {
+                let cb = attenuation.min(1440.) as i16;
+                gens.push((GEN_INITIAL_ATTENUATION, cb.to_le_bytes()));
+            }
+            if loop_end > loop_start {
+                gens.push((GEN_SAMPLE_MODES, 3i16.to_le_bytes()));
+            }
+            if channels == 2 {
+                let pan: i16 = if channel == 0 { -500 } else { 500 };
+                gens.push((GEN_PAN, pan.to_le_bytes()));
+            }
+            let id = self.samples.len() - 1;
+            gens.push((GEN_SAMPLE_ID, (id as u16).to_le_bytes()));
+            self.zones.push(gens);
+        }
+
+        let gens: usize = self.zones.iter().map(Vec::len).sum();
+        if self.samples.len() >= u16::MAX as usize
+            || gens >= u16::MAX as usize
+            || self.data.len() > u32::MAX as usize
+        {
+            return Err(SfzError::InvalidRegion("too many samples for SF2"));
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, name: &str, w: &mut impl Write) -> io::Result<()> {
+        let mut info = b"INFO".to_vec();
+        riff_chunk(&mut info, b"ifil", &[2, 0, 1, 0]);
+        riff_chunk(&mut info, b"isng", &zstr("EMU8000"));
+        riff_chunk(&mut info, b"INAM", &zstr(name));
+
+        let mut smpl = Vec::with_capacity(self.data.len() * 2);
+        for point in &self.data {
+            smpl.extend_from_slice(&point.to_le_bytes());
+        }
+        let mut sdta = b"sdta".to_vec();
+        riff_chunk(&mut sdta, b"smpl", &smpl);
+
+        let mut pdta = b"pdta".to_vec();
+        riff_chunk(&mut pdta, b"phdr", &self.presets(name));
+        // one preset zone pointing at the instrument
+        riff_chunk(&mut pdta, b"pbag", &[0, 0, 0, 0, 1, 0, 0, 0]);
+        riff_chunk(&mut pdta, b"pmod", &[0; 10]);
+        let mut pgen = vec![];
+        generator(&mut pgen, GEN_INSTRUMENT, [0, 0]);
+        generator(&mut pgen, 0, [0, 0]);
+        riff_chunk(&mut pdta, b"pgen", &pgen);
+        riff_chunk(&mut pdta, b"inst", &self.instruments(name));
+        riff_chunk(&mut pdta, b"ibag", &self.bags());
+        riff_chunk(&mut pdta, b"imod", &[0; 10]);
+        let mut igen = vec![];
+        for (oper, amount) in self.zones.iter().flatten() {
+            generator(&mut igen, *oper, *amount);
+        }
+        generator(&mut igen, 0, [0, 0]);
+        riff_chunk(&mut pdta, b"igen", &igen);
+        riff_chunk(&mut pdta, b"shdr", &self.sample_headers());
+
+        let mut body = b"sfbk".to_vec();
+        riff_chunk(&mut body, b"LIST", &info);
+        riff_chunk(&mut body, b"LIST", &sdta);
+        riff_chunk(&mut body, b"LIST", &pdta);
+        if body.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sample data too large for SF2",
+            ));
+        }
+
+        let mut riff = vec![];
+        riff_chunk(&mut riff, b"RIFF", &body);
+        w.write_all(&riff)
+    }
+
+    // the preset and the terminal EOP record
+    fn presets(&self, name: &str) -> Vec<u8> {
+        let mut phdr = vec![];
+        for (name, bag) in &[(name, 0u16), ("EOP", 1)] {
+            phdr.extend_from_slice(&fixed_name(name));
+            phdr.extend_from_slice(&[0; 4]); // preset and bank 0
+            phdr.extend_from_slice(&bag.to_le_bytes());
+            phdr.extend_from_slice(&[0; 12]); // library, genre, morphology
+        }
+
+        phdr
+    }
+
+    fn instruments(&self, name: &str) -> Vec<u8> {
+        let mut inst = vec![];
+        for (name, bag) in &[(name, 0u16), ("EOI", self.zones.len() as u16)] {
+            inst.extend_from_slice(&fixed_name(name));
+            inst.extend_from_slice(&bag.to_le_bytes());
+        }
+
+        inst
+    }
+
+    // each zone's first generator, then the terminal bag
+    fn bags(&self) -> Vec<u8> {
+        let mut ibag = vec![];
+        let mut gen = 0u16;
+        for zone in self.zones.iter().map(Vec::len).chain(Some(0)) {
+            ibag.extend_from_slice(&gen.to_le_bytes());
+            ibag.extend_from_slice(&[0, 0]);
+            gen += zone as u16;
+        }
+
+        ibag
+    }
+
+    fn sample_headers(&self) -> Vec<u8> {
+        let mut shdr = vec![];
+        for s in &self.samples {
+            shdr.extend_from_slice(&fixed_name(&s.name));
+            for v in &[s.start, s.end, s.loop_start, s.loop_end, s.sample_rate]
+            {
+                shdr.extend_from_slice(&v.to_le_bytes());
+            }
+            shdr.push(s.root_key);
+            shdr.push(0); // pitch correction
+            shdr.extend_from_slice(&s.link.to_le_bytes());
+            shdr.extend_from_slice(&s.sample_type.to_le_bytes());
+        }
+        shdr.extend_from_slice(&fixed_name("EOS"));
+        shdr.extend_from_slice(&[0; 26]);
+
+        shdr
+    }
+}
+
+// AIFF-C is fine when it holds integer samples, in either byte order.
+// whether they're little endian, None when they aren't integers
+fn integer_byte_order(compression: Option<&Compression>) -> Option<bool> {
+    match compression.map(|c| &c.compression_type) {
+        None => Some(false),
+        Some(b"NONE") | Some(b"twos") | Some(b"in24") | Some(b"in32") => {
+            Some(false)
+        }
+        Some(b"sowt") | Some(b"42ni") | Some(b"23ni") => Some(true),
+        _ => None,
+    }
+}
+
+// the top 16 bits of a sample point
+fn to_i16(point: &[u8], little_endian: bool) -> i16 {
+    match (point, little_endian) {
+        ([b], _) => i16::from_be_bytes([*b, 0]),
+        ([hi, lo, ..], false) => i16::from_be_bytes([*hi, *lo]),
+        ([.., lo, hi], true) => i16::from_be_bytes([*hi, *lo]),
+        ([], _) => 0,
+    }
+}
+
+// RIFF is little endian and pads odd chunks like AIFF
+fn riff_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn generator(out: &mut Vec<u8>, oper: u16, amount: [u8; 2]) {
+    out.extend_from_slice(&oper.to_le_bytes());
+    out.extend_from_slice(&amount);
+}
+
+// zero terminated with an even length, for the INFO strings
+fn zstr(s: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = s.bytes().filter(u8::is_ascii).take(255).collect();
+    bytes.push(0);
+    if bytes.len() % 2 == 1 {
+        bytes.push(0);
+    }
+
+    bytes
+}
+
+// names in the pdta records are 20 bytes, zero padded
+fn fixed_name(s: &str) -> [u8; 20] {
+    let mut name = [0; 20];
+    for (to, from) in
+        name.iter_mut().zip(s.bytes().filter(u8::is_ascii)).take(19)
+    {
+        *to = from;
+    }
+
+    name
+}
+
+// reads every AIFF in `files` and writes them to `sf2_path` as one
+// instrument named after the SF2 file. returns the regions the zones were
+// made from
+pub fn export_sf2<P: AsRef<Path>>(
+    files: &[P],
+    sf2_path: &Path,
+) -> Result<Vec<SfzRegion>, SfzError> {
+    let stem = |p: &Path| {
+        p.file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    let mut sf2 = SoundFont::default();
+    let mut regions = Vec::with_capacity(files.len());
+    for file in files {
+        let file = file.as_ref();
+        let mut reader = AiffReader::new(fs::File::open(file)?);
+        reader.read_all_form_data()?;
+        let form = reader
+            .form()
+            .as_ref()
+            .ok_or_else(|| SfzError::NoFormChunk(PathBuf::from(file)))?;
+
+        let region = SfzRegion::from_form(&file.to_string_lossy(), form)?;
+        sf2.add_region(&stem(file), &region, form, file)?;
+        regions.push(region);
+    }
+
+    let mut out = io::BufWriter::new(fs::File::create(sf2_path)?);
+    sf2.write(&stem(sf2_path), &mut out)?;
+    out.flush()?;
+
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compression(id: &[u8; 4]) -> Compression {
+        Compression {
+            compression_type: *id,
+            name: String::new(),
+        }
+    }
+
+    #[test]
+    fn integer_samples() {
+        let order = |id| integer_byte_order(Some(&compression(id)));
+        assert_eq!(integer_byte_order(None), Some(false));
+        assert_eq!(order(b"NONE"), Some(false));
+        assert_eq!(order(b"sowt"), Some(true));
+        assert_eq!(order(b"fl32"), None);
+        assert_eq!(order(b"ima4"), None);
+
+        assert_eq!(to_i16(&[0x12, 0x34], false), 0x1234);
+        assert_eq!(to_i16(&[0x34, 0x12], true), 0x1234);
+        assert_eq!(to_i16(&[0x12, 0x34, 0x56], false), 0x1234);
+        assert_eq!(to_i16(&[0x56, 0x34, 0x12], true), 0x1234);
+        assert_eq!(to_i16(&[0x80], true), -0x8000);
+    }
+}
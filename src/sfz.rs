@@ -0,0 +1,746 @@
+// SFZ instrument definitions from, and back into, AIFF INST / MARK data.
+// every AIFF becomes one <region>. the mapping is
+//
+//   INST low_note / high_note         lokey / hikey
+//   INST low_velocity / high_velocity lovel / hivel
+//   INST base_note                    pitch_keycenter
+//   INST detune                       tune (cents)
+//   INST gain                         volume (dB)
+//   INST sustain_loop via MARK        loop_start / loop_end, loop_mode,
+//                                     loop_type (forward or alternate)
+//
+// the sustain loop maps to loop_sustain, which like AIFF loops while the key
+// is held and then plays on to the end. SFZ has no release loop, so that one
+// isn't exported. AIFF loop ends are the frame after the loop while SFZ
+// loop_end is the last frame of the loop, hence the off by one conversions
+use super::{
+    chunks::{ChunkError, FormChunk, InstrumentChunk, Loop, PlayMode},
+    ids,
+    options::{ChunkLoad, ReaderOptions},
+    reader::AiffReader,
+};
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum SfzError {
+    Io(io::Error),
+    Chunk(ChunkError),
+    Parse(usize, String), // line, message
+    NoFormChunk(PathBuf),
+    InvalidRegion(&'static str),
+    Unsupported(PathBuf, &'static str), // a file the format can't hold
+}
+
+impl fmt::Display for SfzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SfzError::Io(e) => write!(f, "{}", e),
+            SfzError::Chunk(e) => write!(f, "invalid AIFF data: {:?}", e),
+            SfzError::Parse(line, msg) => write!(f, "line {}: {}", line, msg),
+            SfzError::NoFormChunk(path) => {
+                write!(f, "no FORM chunk in {}", path.display())
+            }
+            SfzError::InvalidRegion(msg) => {
+                write!(f, "invalid region: {}", msg)
+            }
+            SfzError::Unsupported(path, msg) => {
+                write!(f, "{}: {}", path.display(), msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SfzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SfzError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SfzError {
+    fn from(e: io::Error) -> Self {
+        SfzError::Io(e)
+    }
+}
+
+impl From<ChunkError> for SfzError {
+    fn from(e: ChunkError) -> Self {
+        SfzError::Chunk(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SfzLoopMode {
+    NoLoop,
+    OneShot,
+    LoopContinuous,
+    LoopSustain,
+}
+
+impl SfzLoopMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            SfzLoopMode::NoLoop => "no_loop",
+            SfzLoopMode::OneShot => "one_shot",
+            SfzLoopMode::LoopContinuous => "loop_continuous",
+            SfzLoopMode::LoopSustain => "loop_sustain",
+        }
+    }
+
+    fn parse(s: &str) -> Option<SfzLoopMode> {
+        match s {
+            "no_loop" => Some(SfzLoopMode::NoLoop),
+            "one_shot" => Some(SfzLoopMode::OneShot),
+            "loop_continuous" => Some(SfzLoopMode::LoopContinuous),
+            "loop_sustain" => Some(SfzLoopMode::LoopSustain),
+            _ => None,
+        }
+    }
+}
+
+// opcodes that weren't in the file are None, so importing only touches
+// what the SFZ specifies
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SfzRegion {
+    pub sample: String,
+    pub lokey: Option<u8>,
+    pub hikey: Option<u8>,
+    pub lovel: Option<u8>,
+    pub hivel: Option<u8>,
+    pub pitch_keycenter: Option<u8>,
+    pub tune: Option<i8>,
+    pub volume: Option<f64>,
+    pub loop_mode: Option<SfzLoopMode>,
+    pub alternate_loop: bool, // loop_type=alternate, i.e. ping-pong
+    pub loop_start: Option<u32>,
+    pub loop_end: Option<u32>,
+}
+
+impl SfzRegion {
+    // a form without an INST chunk is exported with the INST defaults
+    pub fn from_form(
+        sample: &str,
+        form: &FormChunk,
+    ) -> Result<SfzRegion, ChunkError> {
+        let default_inst = InstrumentChunk::default();
+        let inst = form.instrument().as_ref().unwrap_or(&default_inst);
+        let sustain = inst.sustain_loop.resolve(&form.markers())?;
+
+        let mut region = SfzRegion {
+            sample: sample.to_owned(),
+            lokey: Some(clamp_midi(inst.low_note)),
+            hikey: Some(clamp_midi(inst.high_note)),
+            lovel: Some(clamp_midi(inst.low_velocity)),
+            hivel: Some(clamp_midi(inst.high_velocity)),
+            pitch_keycenter: Some(clamp_midi(inst.base_note)),
+            tune: Some(inst.detune),
+            volume: Some(inst.gain as f64),
+            loop_mode: Some(SfzLoopMode::NoLoop),
+            ..SfzRegion::default()
+        };
+
+        if let Some(points) = sustain {
+            region.loop_mode = Some(SfzLoopMode::LoopSustain);
+            region.alternate_loop =
+                points.play_mode == PlayMode::ForwardBackward;
+            region.loop_start = Some(points.start);
+            region.loop_end = Some(points.end - 1);
+        }
+
+        Ok(region)
+    }
+
+    // writes the region's opcodes into the INST chunk, creating it if
+    // needed. the loop reuses the sustain loop's markers when it has some
+    pub fn apply_to(&self, form: &mut FormChunk) -> Result<(), SfzError> {
+        let mut inst = form.instrument().clone().unwrap_or_default();
+        let as_midi = |v: u8| v.min(127) as i8;
+
+        if let Some(v) = self.lokey {
+            inst.low_note = as_midi(v);
+        }
+        if let Some(v) = self.hikey {
+            inst.high_note = as_midi(v);
+        }
+        if let Some(v) = self.lovel {
+            inst.low_velocity = as_midi(v);
+        }
+        if let Some(v) = self.hivel {
+            inst.high_velocity = as_midi(v);
+        }
+        if let Some(v) = self.pitch_keycenter {
+            inst.base_note = as_midi(v);
+        }
+        if let Some(v) = self.tune {
+            inst.detune = v.clamp(-50, 50);
+        }
+        if let Some(v) = self.volume {
+            inst.gain = v.round() as i16;
+        }
+
+        let looping = match self.loop_mode {
+            Some(SfzLoopMode::LoopSustain)
+            | Some(SfzLoopMode::LoopContinuous) => true,
+            Some(_) => false,
+            // SFZ loops whenever the sample has loop points and no mode is
+            // given, so follow that
+            None => self.loop_start.is_some() && self.loop_end.is_some(),
+        };
+
+        match (looping, self.loop_start, self.loop_end) {
+            (true, Some(start), Some(end)) => {
+                // the AIFF end marker goes on the frame after the loop
+                let end = end.checked_add(1).ok_or(SfzError::InvalidRegion(
+                    "loop_end is past the last possible frame",
+                ))?;
+                let old = inst.sustain_loop;
+                let markers = form.markers();
+                let reuse = old.begin_loop != old.end_loop
+                    && markers.get(old.begin_loop).is_some()
+                    && markers.get(old.end_loop).is_some();
+
+                let (begin_loop, end_loop) = if reuse {
+                    form.move_marker(old.begin_loop, start)?;
+                    form.move_marker(old.end_loop, end)?;
+                    (old.begin_loop, old.end_loop)
+                } else {
+                    (
+                        form.add_marker(start, "Sustain Loop Start")?,
+                        form.add_marker(end, "Sustain Loop End")?,
+                    )
+                };
+
+                inst.sustain_loop = Loop {
+                    play_mode: if self.alternate_loop {
+                        PlayMode::ForwardBackward
+                    } else {
+                        PlayMode::Forward
+                    },
+                    begin_loop,
+                    end_loop,
+                };
+            }
+            (false, _, _) if self.loop_mode.is_some() => {
+                inst.sustain_loop.play_mode = PlayMode::NoLooping;
+            }
+            _ => (),
+        }
+
+        form.set_instrument(inst);
+        Ok(())
+    }
+}
+
+impl fmt::Display for SfzRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "<region>")?;
+        writeln!(f, "sample={}", self.sample)?;
+
+        let opcodes: [(&str, Option<String>); 10] = [
+            ("lokey", self.lokey.map(|v| v.to_string())),
+            ("hikey", self.hikey.map(|v| v.to_string())),
+            ("lovel", self.lovel.map(|v| v.to_string())),
+            ("hivel", self.hivel.map(|v| v.to_string())),
+            (
+                "pitch_keycenter",
+                self.pitch_keycenter.map(|v| v.to_string()),
+            ),
+            ("tune", self.tune.map(|v| v.to_string())),
+            ("volume", self.volume.map(|v| v.to_string())),
+            ("loop_mode", self.loop_mode.map(|m| m.as_str().to_owned())),
+            ("loop_start", self.loop_start.map(|v| v.to_string())),
+            ("loop_end", self.loop_end.map(|v| v.to_string())),
+        ];
+        for (name, value) in opcodes.iter() {
+            if let Some(v) = value {
+                writeln!(f, "{}={}", name, v)?;
+            }
+        }
+        if self.alternate_loop {
+            writeln!(f, "loop_type=alternate")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn clamp_midi(v: i8) -> u8 {
+    v.max(0) as u8
+}
+
+// only what a region is made from. the sound data is indexed, not loaded
+fn region_options() -> ReaderOptions {
+    ReaderOptions::new()
+        .default_load(ChunkLoad::Skip)
+        .load(*ids::COMMON, ChunkLoad::Full)
+        .load(*ids::MARKER, ChunkLoad::Full)
+        .load(*ids::INSTRUMENT, ChunkLoad::Full)
+        .load(*ids::SOUND, ChunkLoad::Index)
+}
+
+fn read_form(
+    path: &Path,
+    options: ReaderOptions,
+) -> Result<AiffReader<fs::File>, SfzError> {
+    let mut reader = AiffReader::with_options(fs::File::open(path)?, options);
+    reader.read()?;
+    if reader.form().is_none() {
+        return Err(SfzError::NoFormChunk(path.to_owned()));
+    }
+
+    Ok(reader)
+}
+
+// sample paths are written relative to the SFZ file's directory when
+// possible, with forward slashes as the format expects
+fn sample_path(sfz_dir: &Path, file: &Path) -> String {
+    let path = file.strip_prefix(sfz_dir).unwrap_or(file);
+    path.to_string_lossy().replace('\\', "/")
+}
+
+pub fn write_sfz(regions: &[SfzRegion], w: &mut impl Write) -> io::Result<()> {
+    writeln!(w, "// generated by aiff-rs from AIFF INST / MARK chunks")?;
+    for region in regions {
+        writeln!(w)?;
+        write!(w, "{}", region)?;
+    }
+
+    Ok(())
+}
+
+// reads every AIFF in `files` and writes one region per file to `sfz_path`
+pub fn export_sfz<P: AsRef<Path>>(
+    files: &[P],
+    sfz_path: &Path,
+) -> Result<Vec<SfzRegion>, SfzError> {
+    let sfz_dir = sfz_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut regions = Vec::with_capacity(files.len());
+    for file in files {
+        let file = file.as_ref();
+        let reader = read_form(file, region_options())?;
+        let form = reader.form().as_ref().unwrap();
+        regions.push(SfzRegion::from_form(&sample_path(sfz_dir, file), form)?);
+    }
+
+    let mut out = io::BufWriter::new(fs::File::create(sfz_path)?);
+    write_sfz(&regions, &mut out)?;
+    out.flush()?;
+
+    Ok(regions)
+}
+
+// c4 is middle C (60), as in the SFZ spec
+fn parse_note(value: &str) -> Option<u8> {
+    if let Ok(n) = value.parse::<i16>() {
+        return if (0..=127).contains(&n) {
+            Some(n as u8)
+        } else {
+            None
+        };
+    }
+
+    let lower = value.to_ascii_lowercase();
+    let mut chars = lower.chars();
+    let base = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' if rest.len() > 1 => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave: i16 = octave.parse().ok()?;
+    let note = (octave + 1) * 12 + base + accidental;
+
+    if (0..=127).contains(&note) {
+        Some(note as u8)
+    } else {
+        None
+    }
+}
+
+fn apply_opcode(
+    region: &mut SfzRegion,
+    name: &str,
+    value: &str,
+    line: usize,
+) -> Result<(), SfzError> {
+    let invalid =
+        || SfzError::Parse(line, format!("invalid {}={}", name, value));
+    let note = || parse_note(value).ok_or_else(invalid);
+    let velocity = || {
+        value
+            .parse::<u8>()
+            .ok()
+            .filter(|v| *v <= 127)
+            .ok_or_else(invalid)
+    };
+    let frames = || value.parse::<u32>().map_err(|_| invalid());
+
+    match name {
+        "sample" => region.sample = value.to_owned(),
+        "lokey" => region.lokey = Some(note()?),
+        "hikey" => region.hikey = Some(note()?),
+        "key" => {
+            let n = note()?;
+            region.lokey = Some(n);
+            region.hikey = Some(n);
+            region.pitch_keycenter = Some(n);
+        }
+        "pitch_keycenter" => region.pitch_keycenter = Some(note()?),
+        "lovel" => region.lovel = Some(velocity()?),
+        "hivel" => region.hivel = Some(velocity()?),
+        "tune" => {
+            let cents = value.parse::<i32>().map_err(|_| invalid())?;
+            region.tune = Some(cents.clamp(-50, 50) as i8);
+        }
+        "volume" => region.volume = Some(value.parse().map_err(|_| invalid())?),
+        "loop_mode" | "loopmode" => {
+            region.loop_mode =
+                Some(SfzLoopMode::parse(value).ok_or_else(invalid)?)
+        }
+        "loop_type" | "looptype" => {
+            region.alternate_loop = value == "alternate"
+        }
+        "loop_start" | "loopstart" => region.loop_start = Some(frames()?),
+        "loop_end" | "loopend" => region.loop_end = Some(frames()?),
+        // everything else has no INST / MARK equivalent
+        _ => (),
+    }
+
+    Ok(())
+}
+
+#[derive(PartialEq)]
+enum Header {
+    None,
+    Control,
+    Global,
+    Group,
+    Region,
+    Other,
+}
+
+#[derive(Default)]
+struct SfzParser {
+    regions: Vec<SfzRegion>,
+    default_path: String,
+    global: SfzRegion,
+    group: SfzRegion,
+    in_group: bool,
+}
+
+impl SfzParser {
+    fn header(&mut self, name: &str) -> Header {
+        match name {
+            "control" => Header::Control,
+            "global" => {
+                self.global = SfzRegion::default();
+                self.in_group = false;
+                Header::Global
+            }
+            "group" | "master" => {
+                self.group = self.global.clone();
+                self.in_group = true;
+                Header::Group
+            }
+            "region" => {
+                let base = if self.in_group {
+                    &self.group
+                } else {
+                    &self.global
+                };
+                self.regions.push(base.clone());
+                Header::Region
+            }
+            _ => Header::Other,
+        }
+    }
+
+    fn opcode(
+        &mut self,
+        header: &Header,
+        name: &str,
+        value: &str,
+        line: usize,
+    ) -> Result<(), SfzError> {
+        let target = match header {
+            Header::Global => &mut self.global,
+            Header::Group => &mut self.group,
+            Header::Region => self.regions.last_mut().unwrap(),
+            Header::Control if name == "default_path" => {
+                self.default_path = value.replace('\\', "/");
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        apply_opcode(target, name, value, line)
+    }
+}
+
+// reads the <region>s of an SFZ file. opcodes set under <global> and
+// <group> are inherited by the regions that follow, and default_path from
+// <control> is prepended to sample paths
+pub fn parse_sfz(text: &str) -> Result<Vec<SfzRegion>, SfzError> {
+    let mut parser = SfzParser::default();
+    let mut header = Header::None;
+
+    for (idx, raw_line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = match raw_line.find("//") {
+            Some(pos) => &raw_line[..pos],
+            None => raw_line,
+        };
+
+        // (name, value) pairs. values may contain spaces (sample paths do),
+        // so a token without '=' continues the previous value
+        let mut opcodes: Vec<(&str, String)> = vec![];
+        let mut rest = line.trim();
+        while !rest.is_empty() {
+            if rest.starts_with('<') {
+                for (name, value) in opcodes.drain(..) {
+                    parser.opcode(&header, name, &value, line_no)?;
+                }
+                let end = rest.find('>').ok_or_else(|| {
+                    SfzError::Parse(line_no, "unterminated header".to_owned())
+                })?;
+                header = parser.header(&rest[1..end]);
+                rest = rest[end + 1..].trim_start();
+                continue;
+            }
+
+            let token_end = rest
+                .find(|c: char| c.is_whitespace() || c == '<')
+                .unwrap_or(rest.len());
+            let token = &rest[..token_end];
+            rest = rest[token_end..].trim_start();
+
+            match (token.find('='), opcodes.last_mut()) {
+                (Some(eq), _) => {
+                    opcodes.push((&token[..eq], token[eq + 1..].to_owned()))
+                }
+                (None, Some((_, value))) => {
+                    value.push(' ');
+                    value.push_str(token);
+                }
+                (None, None) => {
+                    return Err(SfzError::Parse(
+                        line_no,
+                        format!("unexpected '{}'", token),
+                    ))
+                }
+            }
+        }
+        for (name, value) in opcodes.drain(..) {
+            parser.opcode(&header, name, &value, line_no)?;
+        }
+    }
+
+    let default_path = parser.default_path;
+    Ok(parser
+        .regions
+        .into_iter()
+        .map(|mut region| {
+            region.sample = format!("{}{}", default_path, region.sample);
+            region
+        })
+        .collect())
+}
+
+// writes each region's opcodes into the INST and MARK chunks of the AIFF it
+// references. the rest of each file, unknown chunks and the ID3 tag
+// included, is written back as it was read. sample paths are resolved
+// against the SFZ file's directory. returns the files that were updated
+pub fn import_sfz(sfz_path: &Path) -> Result<Vec<PathBuf>, SfzError> {
+    let sfz_dir = sfz_path.parent().unwrap_or_else(|| Path::new(""));
+    let regions = parse_sfz(&fs::read_to_string(sfz_path)?)?;
+
+    let mut updated = vec![];
+    for region in regions.iter().filter(|r| !r.sample.is_empty()) {
+        let path = sfz_dir.join(&region.sample);
+        // everything is loaded, it's all written back
+        let mut reader = read_form(&path, ReaderOptions::new())?;
+        region.apply_to(reader.form_mut().as_mut().unwrap())?;
+
+        // write next to the original first so a failed write can't lose it
+        let tmp_path = path.with_extension("sfz-import.tmp");
+        let mut out = io::BufWriter::new(fs::File::create(&tmp_path)?);
+        reader.write(&mut out)?;
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp_path, &path)?;
+
+        updated.push(path);
+    }
+
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::CommonChunk;
+
+    const PATCH: &str = "\
+<control> default_path=Samples\\Piano\\
+<global> lovel=1 // everything below inherits this
+<group> hivel=64 loop_mode=loop_sustain
+<region> sample=C4 soft.aif key=c4 loop_start=100 loop_end=199
+<region> sample=D4 soft.aif lokey=d4 hikey=e4 pitch_keycenter=62
+<group> hivel=127 tune=-70
+<region> sample=C4 loud.aif key=60 loop_type=alternate
+";
+
+    #[test]
+    fn parses_regions() {
+        let regions = parse_sfz(PATCH).unwrap();
+        assert_eq!(regions.len(), 3);
+
+        assert_eq!(
+            regions[0],
+            SfzRegion {
+                sample: "Samples/Piano/C4 soft.aif".to_owned(),
+                lokey: Some(60),
+                hikey: Some(60),
+                lovel: Some(1),
+                hivel: Some(64),
+                pitch_keycenter: Some(60),
+                loop_mode: Some(SfzLoopMode::LoopSustain),
+                loop_start: Some(100),
+                loop_end: Some(199),
+                ..SfzRegion::default()
+            }
+        );
+        assert_eq!(regions[1].lokey, Some(62));
+        assert_eq!(regions[1].hikey, Some(64));
+        assert_eq!(regions[1].loop_mode, Some(SfzLoopMode::LoopSustain));
+
+        // a new group starts over from <global>
+        assert_eq!(regions[2].lovel, Some(1));
+        assert_eq!(regions[2].hivel, Some(127));
+        assert_eq!(regions[2].loop_mode, None);
+        assert_eq!(regions[2].tune, Some(-50));
+        assert!(regions[2].alternate_loop);
+    }
+
+    #[test]
+    fn note_names() {
+        assert_eq!(parse_note("c4"), Some(60));
+        assert_eq!(parse_note("C#4"), Some(61));
+        assert_eq!(parse_note("db4"), Some(61));
+        assert_eq!(parse_note("a-1"), Some(9));
+        assert_eq!(parse_note("g9"), Some(127));
+        assert_eq!(parse_note("g#9"), None);
+        assert_eq!(parse_note("128"), None);
+        assert_eq!(parse_note("h4"), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err =
+            parse_sfz("<region> sample=a.aif\n<region lokey=1").unwrap_err();
+        assert!(matches!(err, SfzError::Parse(2, _)));
+        let err = parse_sfz("<region> sample=a.aif lovel=200").unwrap_err();
+        assert!(matches!(err, SfzError::Parse(1, _)));
+        let err = parse_sfz("stray <region>").unwrap_err();
+        assert!(matches!(err, SfzError::Parse(1, _)));
+    }
+
+    #[test]
+    fn written_regions_parse_back() {
+        let regions = parse_sfz(PATCH).unwrap();
+        let mut out = vec![];
+        write_sfz(&regions, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(parse_sfz(&text).unwrap(), regions);
+    }
+
+    #[test]
+    fn loop_round_trips_through_inst() {
+        let mut form = FormChunk::new();
+        form.set_common(CommonChunk {
+            size: 18,
+            num_channels: 1,
+            num_sample_frames: 1000,
+            bit_rate: 16,
+            sample_rate: 44100.,
+        });
+
+        let region = &parse_sfz(PATCH).unwrap()[0];
+        region.apply_to(&mut form).unwrap();
+        let inst = form.instrument().as_ref().unwrap();
+        assert_eq!(inst.sustain_loop.play_mode, PlayMode::Forward);
+        // the end marker is on the frame after the loop
+        let markers = form.markers();
+        let end = markers.get(inst.sustain_loop.end_loop).unwrap();
+        assert_eq!(end.position, 200);
+
+        let exported = SfzRegion::from_form(&region.sample, &form).unwrap();
+        assert_eq!(exported.loop_start, Some(100));
+        assert_eq!(exported.loop_end, Some(199));
+        assert_eq!(exported.lokey, Some(60));
+    }
+
+    #[test]
+    fn import_then_export() {
+        let dir = std::env::temp_dir().join("aiff-sfz-import-then-export");
+        fs::create_dir_all(&dir).unwrap();
+
+        // mono 8 bit, 10 frames, no INST or MARK yet
+        let mut aiff = b"FORM\0\0\0\x3EAIFFCOMM\0\0\0\x12".to_vec();
+        aiff.extend_from_slice(&[0, 1, 0, 0, 0, 10, 0, 8]);
+        aiff.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        aiff.extend_from_slice(b"SSND\0\0\0\x12\0\0\0\0\0\0\0\0");
+        aiff.extend(0..10);
+        fs::write(dir.join("a.aif"), &aiff).unwrap();
+        let sfz = "<region> sample=a.aif key=62 loop_mode=loop_sustain \
+                   loop_start=2 loop_end=4";
+        fs::write(dir.join("a.sfz"), sfz).unwrap();
+
+        let updated = import_sfz(&dir.join("a.sfz")).unwrap();
+        assert_eq!(updated, [dir.join("a.aif")]);
+        let regions = export_sfz(&[dir.join("a.aif")], &dir.join("b.sfz"));
+        let region = &regions.unwrap()[0];
+        assert_eq!(region.pitch_keycenter, Some(62));
+        assert_eq!((region.loop_start, region.loop_end), (Some(2), Some(4)));
+
+        // the sound data is written back along with the new chunks
+        let mut reader =
+            AiffReader::new(fs::File::open(dir.join("a.aif")).unwrap());
+        reader.read().unwrap();
+        let form = reader.form().as_ref().unwrap();
+        let sound = form.sound().as_ref().unwrap();
+        assert_eq!(sound.sound_data, (0..10).collect::<Vec<u8>>());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn loop_end_overflow() {
+        let region = SfzRegion {
+            loop_start: Some(0),
+            loop_end: Some(u32::MAX),
+            ..SfzRegion::default()
+        };
+        assert!(matches!(
+            region.apply_to(&mut FormChunk::new()),
+            Err(SfzError::InvalidRegion(_))
+        ));
+    }
+}
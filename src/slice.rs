@@ -0,0 +1,156 @@
+// parsing straight from memory, e.g. a whole file read into a Vec or a
+// memory mapped file. nothing is copied: chunks are views into the input,
+// the chunk list is walked lazily and samples decode on the fly, so reading
+// a large file allocates nothing beyond whatever the samples are collected
+// into.
+//
+//     let slice = AiffSlice::parse(&mmap[..])?;
+//     let samples: Vec<i16> = slice.samples().collect();
+use super::{
+    chunks::{ChunkError, CommonChunk},
+    detect::{self, DetectError, FormKind},
+    ids::{self, ChunkID},
+    macroman::{self, TextDecoding},
+    probe::{self, Compression},
+    samples::SampleType,
+    walk::{self, ChunkWalk},
+};
+use std::{borrow::Cow, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SliceChunk<'a> {
+    pub id: ChunkID,
+    pub offset: usize, // of the chunk id in the input
+    pub size: u32,     // declared size
+    // the chunk body, shorter than `size` if the input is truncated
+    pub data: &'a [u8],
+}
+
+// walks the chunks of a FORM body. stops at the first header that doesn't
+// fit in the input
+#[derive(Debug, Clone)]
+pub struct SliceChunks<'a> {
+    bytes: &'a [u8],
+    walk: ChunkWalk,
+}
+
+impl<'a> SliceChunks<'a> {
+    fn new(bytes: &'a [u8], form_end: usize) -> SliceChunks<'a> {
+        SliceChunks {
+            bytes,
+            walk: ChunkWalk::new(form_end as u64),
+        }
+    }
+}
+
+impl<'a> Iterator for SliceChunks<'a> {
+    type Item = SliceChunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pos = self.walk.next_header()? as usize;
+        let (id, size) = walk::decode_header(self.bytes.get(pos..pos + 8)?);
+        let header = self.walk.advance(id, size);
+
+        let data_end = self.walk.body_end(&header) as usize;
+        Some(SliceChunk {
+            id,
+            offset: pos,
+            size,
+            data: &self.bytes[pos + 8..data_end],
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AiffSlice<'a> {
+    bytes: &'a [u8],
+    form_end: usize,
+    pub kind: FormKind,
+    pub common: CommonChunk,
+    pub compression: Option<Compression>,
+    sound: Option<SliceChunk<'a>>,
+}
+
+impl<'a> AiffSlice<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<AiffSlice<'a>, ChunkError> {
+        let kind = detect::detect_with_reason(bytes).map_err(|e| match e {
+            DetectError::OtherIff(t) => ChunkError::InvalidFormType(t),
+            DetectError::Iff8SVX => ChunkError::InvalidFormType(*b"8SVX"),
+            DetectError::Unknown(id) => ChunkError::InvalidID(id),
+            _ => ChunkError::InvalidData("not an AIFF file"),
+        })?;
+
+        // an unfinished recording leaves a 0 size, so use all of the input
+        let (_, form_size) = walk::decode_header(bytes);
+        let form_end = walk::form_end(form_size, bytes.len() as u64) as usize;
+
+        let chunks = SliceChunks::new(bytes, form_end);
+        let comm = chunks
+            .clone()
+            .find(|c| &c.id == ids::COMMON)
+            .ok_or(ChunkError::InvalidData("missing COMM chunk"))?;
+
+        Ok(AiffSlice {
+            bytes,
+            form_end,
+            kind,
+            common: CommonChunk::decode(comm.size as i32, comm.data)?,
+            compression: probe::decode_compression(comm.data),
+            sound: chunks.clone().find(|c| &c.id == ids::SOUND),
+        })
+    }
+
+    pub fn chunks(&self) -> SliceChunks<'a> {
+        SliceChunks::new(self.bytes, self.form_end)
+    }
+
+    // the first chunk with the id
+    pub fn chunk(&self, id: &ChunkID) -> Option<SliceChunk<'a>> {
+        self.chunks().find(|c| &c.id == id)
+    }
+
+    pub fn duration(&self) -> Duration {
+        probe::frames_duration(
+            self.common.num_sample_frames,
+            self.common.sample_rate,
+        )
+    }
+
+    // NAME, AUTH, (c)  or ANNO text, borrowed unless it has non ASCII
+    // characters
+    pub fn text(&self, id: &ChunkID) -> Option<Cow<'a, str>> {
+        let chunk = self.chunk(id)?;
+        macroman::decode_cow(chunk.data, TextDecoding::Lossy).ok()
+    }
+
+    // the sample frames in SSND, after its offset. limited to the frame count
+    // in COMM when the chunk is longer
+    pub fn sound_data(&self) -> Option<&'a [u8]> {
+        let sound = self.sound?;
+        let offset = sound.data.get(..4).map_or(0, |o| {
+            u32::from_be_bytes([o[0], o[1], o[2], o[3]]) as usize
+        });
+        let data = sound.data.get(8 + offset..).unwrap_or(&[]);
+
+        let len = (self.common.num_sample_frames as usize)
+            .saturating_mul(walk::frame_size(&self.common))
+            .min(data.len());
+        Some(&data[..len])
+    }
+
+    // one slice per sample frame, with a point per channel
+    pub fn frames(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let frame_size = walk::frame_size(&self.common).max(1);
+        self.sound_data().unwrap_or(&[]).chunks_exact(frame_size)
+    }
+
+    // interleaved sample points, decoded as they're iterated
+    pub fn samples<T: SampleType + 'a>(&self) -> impl Iterator<Item = T> + 'a {
+        let data = self.sound_data().unwrap_or(&[]);
+        let bit_rate = self.common.bit_rate;
+        let bytes_per_point = walk::point_size(bit_rate).max(1);
+
+        (0..data.len() / bytes_per_point)
+            .map(move |point| T::parse(data, point * bytes_per_point, bit_rate))
+    }
+}
@@ -0,0 +1,460 @@
+// single pass parsing over a plain `Read`, for sources that can't seek:
+// stdin, network bodies, decompressors. chunks are read in file order, and
+// anything not wanted is read and thrown away instead of seeked over.
+//
+// the chunks before SSND are parsed up front. sound data can then be
+// streamed in blocks, which needs COMM to know the frame layout, so a file
+// that puts SSND first can't be streamed and is rejected. chunks after SSND
+// (often the ID3 tag) are parsed by `finish`
+use super::{
+    chunks::{
+        ApplicationSpecificChunk, AudioRecordingChunk, Chunk, ChunkError,
+        CommentsChunk, CommonChunk, FormChunk, InstrumentChunk, MIDIDataChunk,
+        MarkerChunk, TextChunk,
+    },
+    detect::{self, FormKind},
+    ids::{self, ChunkID},
+    options::{ChunkLoad, ReaderOptions},
+    probe::{self, ChunkHeader, Compression},
+    samples::SampleType,
+    walk::{self, read_whole_header, ChunkWalk},
+};
+use seek_bufread::BufReader;
+use std::io::{self, Cursor, Read};
+
+const NO_COMPRESSION: &ChunkID = b"NONE";
+
+pub(crate) fn is_uncompressed(c: &Compression) -> bool {
+    &c.compression_type == NO_COMPRESSION
+}
+
+// compression types that store plain samples a fixed number of bytes each,
+// only in another byte order or format, so the data size follows from the
+// frame count
+pub(crate) fn stores_pcm(c: &Compression) -> bool {
+    matches!(
+        &c.compression_type,
+        b"NONE"
+            | b"twos"
+            | b"sowt"
+            | b"raw "
+            | b"in24"
+            | b"42ni"
+            | b"in32"
+            | b"23ni"
+            | b"fl32"
+            | b"FL32"
+            | b"fl64"
+            | b"FL64"
+    )
+}
+
+// parses a chunk body with the chunk's regular parser, through an in memory
+// reader. the parsers expect to read the size themselves
+fn parse_body<'a, T: Chunk<'a> + 'a>(
+    id: &ChunkID,
+    body: &[u8],
+    options: &ReaderOptions,
+) -> Result<Option<T>, ChunkError> {
+    let mut bytes = Vec::with_capacity(4 + body.len());
+    bytes.extend_from_slice(&(body.len() as i32).to_be_bytes());
+    bytes.extend_from_slice(body);
+
+    T::parse(
+        &mut BufReader::new(Cursor::new(bytes)),
+        *id,
+        options,
+        &mut None,
+    )
+}
+
+// adds a complete chunk body to the form. shared by the readers that can't
+// hand the chunk parsers a seekable source. returns false for chunks it
+// doesn't know, which are left out
+pub(crate) fn add_chunk_body(
+    form: &mut FormChunk,
+    id3v2_tag: &mut Option<id3::Tag>,
+    id: &ChunkID,
+    body: &[u8],
+    options: &ReaderOptions,
+) -> Result<bool, ChunkError> {
+    match id {
+        // decoded directly since AIFF-C COMM chunks are longer than the
+        // parser expects
+        ids::COMMON => {
+            form.set_common(CommonChunk::decode(body.len() as i32, body)?);
+            form.set_compression(probe::decode_compression(body));
+        }
+        ids::MARKER => {
+            if let Some(c) = parse_body::<MarkerChunk>(id, body, options)? {
+                form.add_marker_chunk(c);
+            }
+        }
+        ids::INSTRUMENT => {
+            if let Some(c) = parse_body::<InstrumentChunk>(id, body, options)? {
+                form.set_instrument(c);
+            }
+        }
+        ids::MIDI => form.add_midi_chunk(MIDIDataChunk::new(body.to_vec())),
+        ids::RECORDING => {
+            if let Some(c) =
+                parse_body::<AudioRecordingChunk>(id, body, options)?
+            {
+                form.set_recording(c);
+            }
+        }
+        ids::APPLICATION => {
+            if let Some(c) =
+                parse_body::<ApplicationSpecificChunk>(id, body, options)?
+            {
+                form.add_app_chunk(c);
+            }
+        }
+        ids::COMMENTS => {
+            if let Some(c) = parse_body::<CommentsChunk>(id, body, options)? {
+                form.set_comments(c);
+            }
+        }
+        ids::NAME | ids::AUTHOR | ids::COPYRIGHT | ids::ANNOTATION => {
+            if let Some(c) = parse_body::<TextChunk>(id, body, options)? {
+                form.add_text_chunk(c);
+            }
+        }
+        ids::ID3_CHUNK => {
+            let tag = id3::Tag::read_from(body)
+                .map_err(|_| ChunkError::InvalidData("ID3 tag"))?;
+            *id3v2_tag = Some(tag);
+        }
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+// chunks `add_chunk_body` can parse
+pub(crate) fn is_known(id: &ChunkID) -> bool {
+    matches!(
+        id,
+        ids::COMMON
+            | ids::MARKER
+            | ids::INSTRUMENT
+            | ids::MIDI
+            | ids::RECORDING
+            | ids::APPLICATION
+            | ids::COMMENTS
+            | ids::NAME
+            | ids::AUTHOR
+            | ids::COPYRIGHT
+            | ids::ANNOTATION
+            | ids::ID3_CHUNK
+    )
+}
+
+// interleaved sample points from whole frames of uncompressed sound data
+pub(crate) fn decode_samples<T: SampleType>(
+    data: &[u8],
+    bit_rate: i16,
+    out: &mut Vec<T>,
+) {
+    let bytes_per_point = walk::point_size(bit_rate).max(1);
+    out.extend(
+        (0..data.len() / bytes_per_point)
+            .map(|point| T::parse(data, point * bytes_per_point, bit_rate)),
+    );
+}
+
+// read and thrown away
+fn discard(r: &mut impl Read, len: u64) -> io::Result<u64> {
+    io::copy(&mut r.take(len), &mut io::sink())
+}
+
+enum State {
+    Chunks,
+    Sound { remaining: u64, pad: bool },
+    Done,
+}
+
+pub struct AiffStreamReader<R: Read> {
+    inner: R,
+    kind: FormKind,
+    form: FormChunk,
+    id3v2_tag: Option<id3::Tag>,
+    compression: Option<Compression>,
+    options: ReaderOptions,
+    state: State,
+    chunks: ChunkWalk,
+}
+
+impl<R: Read> AiffStreamReader<R> {
+    pub fn new(inner: R) -> Result<AiffStreamReader<R>, ChunkError> {
+        AiffStreamReader::with_options(inner, ReaderOptions::new())
+    }
+
+    // reads the FORM header. chunks can't be revisited, so indexed chunks
+    // are skipped
+    pub fn with_options(
+        mut inner: R,
+        options: ReaderOptions,
+    ) -> Result<AiffStreamReader<R>, ChunkError> {
+        let mut header = [0; detect::HEADER_SIZE];
+        inner.read_exact(&mut header)?;
+        let (id, form_size) = walk::decode_header(&header);
+        let kind = detect::detect(&header).ok_or(ChunkError::InvalidID(id))?;
+
+        let mut form = FormChunk::new();
+        form.set_form_type(*kind.form_type());
+
+        Ok(AiffStreamReader {
+            inner,
+            kind,
+            form,
+            id3v2_tag: None,
+            compression: None,
+            options,
+            state: State::Chunks,
+            // the length isn't known, an unset FORM size runs to the end of
+            // the stream
+            chunks: ChunkWalk::new(walk::form_end(form_size, u64::MAX)),
+        })
+    }
+
+    // chunk ids to discard instead of parsing
+    pub fn skip(mut self, id: ChunkID) -> AiffStreamReader<R> {
+        self.options = self.options.load(id, ChunkLoad::Skip);
+        self
+    }
+
+    pub fn kind(&self) -> FormKind {
+        self.kind
+    }
+
+    pub fn form(&self) -> &FormChunk {
+        &self.form
+    }
+
+    pub fn id3v2_tag(&self) -> Option<&id3::Tag> {
+        self.id3v2_tag.as_ref()
+    }
+
+    pub fn compression(&self) -> Option<&Compression> {
+        self.compression.as_ref()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // the next chunk header, None at the end of the FORM or the stream.
+    // anything after the FORM, like an appended ID3v1 tag, is left unread
+    fn next_header(&mut self) -> Result<Option<ChunkHeader>, ChunkError> {
+        if self.chunks.next_header().is_none() {
+            return Ok(None);
+        }
+        Ok(read_whole_header(&mut self.inner)?
+            .map(|(id, size)| self.chunks.advance(id, size)))
+    }
+
+    // parses chunks until SSND or the end of the stream, then stops at the
+    // first sample frame. returns whether there is sound data to read
+    pub fn read_until_sound(&mut self) -> Result<bool, ChunkError> {
+        if !matches!(self.state, State::Chunks) {
+            return Ok(matches!(self.state, State::Sound { .. }));
+        }
+
+        while let Some(header) = self.next_header()? {
+            let ChunkHeader { id, size, .. } = header;
+            let pad = size % 2 == 1;
+
+            if &id == ids::SOUND {
+                if self.form.common().is_none() {
+                    return Err(ChunkError::SoundBeforeCommon);
+                }
+
+                let mut offsets = [0; 8];
+                self.inner.read_exact(&mut offsets)?;
+                let offset = u32::from_be_bytes([
+                    offsets[0], offsets[1], offsets[2], offsets[3],
+                ]);
+                discard(&mut self.inner, offset as u64)?;
+
+                // unfinished recordings leave the size at 0, so read on
+                // until the FORM or the stream ends
+                let mut remaining = walk::sound_data_len(size, offset);
+                let end = self.chunks.body_end(&header);
+                if walk::is_unsized(size) && end != u64::MAX {
+                    let start = header.offset + 16 + offset as u64;
+                    remaining = end.saturating_sub(start);
+                }
+                self.state = State::Sound { remaining, pad };
+                return Ok(true);
+            }
+
+            self.read_chunk(id, size)?;
+        }
+
+        self.state = State::Done;
+        Ok(false)
+    }
+
+    fn read_chunk(&mut self, id: ChunkID, size: u32) -> Result<(), ChunkError> {
+        let padded = size as u64 + size as u64 % 2;
+        if !is_known(&id) || !self.options.loads(&id) {
+            discard(&mut self.inner, padded)?;
+            return Ok(());
+        }
+        self.options.check_size(&id, size as u64)?;
+
+        let mut body = vec![];
+        (&mut self.inner).take(size as u64).read_to_end(&mut body)?;
+        if body.len() < size as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        discard(&mut self.inner, padded - size as u64)?;
+
+        if &id == ids::COMMON {
+            self.compression = probe::decode_compression(&body);
+        }
+        add_chunk_body(
+            &mut self.form,
+            &mut self.id3v2_tag,
+            &id,
+            &body,
+            &self.options,
+        )?;
+
+        Ok(())
+    }
+
+    fn frame_size(&self) -> usize {
+        self.form.common().as_ref().map_or(0, walk::frame_size)
+    }
+
+    // fills `buf` with whole sample frames and returns the number of bytes
+    // read, 0 once the sound data is exhausted
+    pub fn read_frames(&mut self, buf: &mut [u8]) -> Result<usize, ChunkError> {
+        if let State::Chunks = self.state {
+            self.read_until_sound()?;
+        }
+        let remaining = match self.state {
+            State::Sound { remaining, .. } => remaining,
+            _ => return Ok(0),
+        };
+
+        let frame_size = self.frame_size().max(1);
+        let want = (buf.len() / frame_size * frame_size)
+            .min(remaining.min(usize::MAX as u64) as usize);
+
+        let mut filled = 0;
+        while filled < want {
+            match self.inner.read(&mut buf[filled..want]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if let State::Sound { remaining, .. } = &mut self.state {
+            *remaining -= filled as u64;
+        }
+        // a partial frame at the end of a truncated stream is dropped
+        Ok(filled / frame_size * frame_size)
+    }
+
+    // decodes up to `max_frames` frames of interleaved sample points into
+    // `out`, returning the number of frames read
+    pub fn read_samples<T: SampleType>(
+        &mut self,
+        max_frames: usize,
+        out: &mut Vec<T>,
+    ) -> Result<usize, ChunkError> {
+        // COMM has to be read before the bit rate is known
+        self.read_until_sound()?;
+        if self
+            .compression
+            .as_ref()
+            .is_some_and(|c| !is_uncompressed(c))
+        {
+            return Err(ChunkError::InvalidData("compressed sound data"));
+        }
+
+        let bit_rate = self.form.common().as_ref().map_or(0, |c| c.bit_rate);
+        let frame_size = self.frame_size().max(1);
+
+        let mut buf = vec![0; max_frames * frame_size];
+        let len = self.read_frames(&mut buf)?;
+        decode_samples(&buf[..len], bit_rate, out);
+
+        Ok(len / frame_size)
+    }
+
+    // skips whatever sound data is left and parses the chunks after it
+    pub fn finish(&mut self) -> Result<(), ChunkError> {
+        if let State::Chunks = self.state {
+            if !self.read_until_sound()? {
+                return Ok(());
+            }
+        }
+        if let State::Sound { remaining, pad } = self.state {
+            let unbounded = remaining == u64::MAX;
+            discard(&mut self.inner, remaining)?;
+            if pad && !unbounded {
+                discard(&mut self.inner, 1)?;
+            }
+        }
+
+        while let Some(header) = self.next_header()? {
+            self.read_chunk(header.id, header.size)?;
+        }
+        self.state = State::Done;
+
+        Ok(())
+    }
+
+    // finishes the stream and hands back everything that was parsed
+    pub fn into_parts(
+        mut self,
+    ) -> Result<(FormChunk, Option<id3::Tag>), ChunkError> {
+        self.finish()?;
+        Ok((self.form, self.id3v2_tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 channel of 8 bit, 4 frames, followed by bytes that aren't a chunk
+    fn file(form_size: u32, sound_size: u32) -> Vec<u8> {
+        let mut bytes = b"FORM".to_vec();
+        bytes.extend_from_slice(&form_size.to_be_bytes());
+        bytes.extend_from_slice(b"AIFFCOMM\0\0\0\x12\0\x01\0\0\0\x04\0\x08");
+        bytes.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"SSND");
+        bytes.extend_from_slice(&sound_size.to_be_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        bytes.extend_from_slice(b"TAG\0\0");
+        bytes
+    }
+
+    fn frames(bytes: &[u8]) -> Vec<u8> {
+        let mut reader = AiffStreamReader::new(bytes).unwrap();
+        let mut buf = [0; 16];
+        let len = reader.read_frames(&mut buf).unwrap();
+        reader.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn stops_at_form_end() {
+        assert_eq!(frames(&file(0x32, 0x0C)), [1, 2, 3, 4]);
+        // an unset SSND size runs to the end of the FORM
+        assert_eq!(frames(&file(0x32, 0)), [1, 2, 3, 4]);
+        // an unset FORM size runs to the end of the stream
+        let bytes = file(0, 0);
+        let mut reader = AiffStreamReader::new(&bytes[..]).unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(reader.read_frames(&mut buf).unwrap(), 9);
+    }
+}
@@ -0,0 +1,498 @@
+// conformance checks against the AIFF 1.3 and AIFF-C specs, for QC of
+// delivered files. unlike the readers nothing is worked around: every
+// problem found ends up in the report, and `passed` says whether any of
+// them break the spec.
+//
+//     let report = aiff::validate(&mut file)?;
+//     if !report.passed() {
+//         for v in report.errors() { eprintln!("{}", v); }
+//     }
+use super::{
+    chunks::{ChunkError, FormChunk, PlayMode},
+    detect::{self, FormKind},
+    diagnostics::Severity,
+    ids::{self, ChunkID},
+    options::ReaderOptions,
+    probe::ChunkHeader,
+    reader::is_plausible_id,
+    stream,
+    walk::{self, ChunkWalk},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{Read, Seek, SeekFrom},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    FormType,       // FORM header with an AIFF or AIFC form type
+    FormSize,       // FORM size matches its chunks and the file
+    ChunkAlignment, // chunks start at even offsets
+    ChunkSize,      // chunks fit in the FORM
+    ChunkData,      // chunk bodies parse
+    RequiredChunk,  // COMM, SSND with sample frames, FVER for AIFF-C
+    ChunkCount,     // chunks allowed at most once
+    SampleSize,     // 1 to 32 bits
+    Channels,       // at least 1
+    SampleRate,     // positive
+    SoundDataSize,  // SSND holds every frame COMM declares
+    MarkerId,       // positive and unique
+    MarkerPosition, // within the sound data
+    LoopMarker,     // INST loops point at existing markers
+    LoopPlayMode,   // INST loops use a play mode from the spec
+    CommentMarker,  // COMT comments point at existing markers
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub chunk: Option<ChunkID>,
+    pub offset: Option<u64>, // of the chunk id
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {:?}", self.severity, self.rule)?;
+        if let Some(id) = &self.chunk {
+            write!(f, " ({})", String::from_utf8_lossy(id))?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at {}", offset)?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ValidationReport {
+    pub kind: Option<FormKind>,
+    pub chunks: Vec<ChunkHeader>,
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    // no errors, warnings are allowed
+    pub fn passed(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Violation> {
+        self.violations
+            .iter()
+            .filter(|v| v.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Violation> {
+        self.violations
+            .iter()
+            .filter(|v| v.severity == Severity::Warning)
+    }
+
+    fn fail(
+        &mut self,
+        rule: Rule,
+        header: Option<&ChunkHeader>,
+        message: String,
+    ) {
+        self.add(rule, Severity::Error, header, message);
+    }
+
+    fn warn(
+        &mut self,
+        rule: Rule,
+        header: Option<&ChunkHeader>,
+        message: String,
+    ) {
+        self.add(rule, Severity::Warning, header, message);
+    }
+
+    fn add(
+        &mut self,
+        rule: Rule,
+        severity: Severity,
+        header: Option<&ChunkHeader>,
+        message: String,
+    ) {
+        self.violations.push(Violation {
+            rule,
+            severity,
+            chunk: header.map(|h| h.id),
+            offset: header.map(|h| h.offset),
+            message,
+        });
+    }
+
+    fn header(&self, id: &ChunkID) -> Option<&ChunkHeader> {
+        self.chunks.iter().find(|h| &h.id == id)
+    }
+}
+
+// chunks that may appear at most once
+const SINGLE_CHUNKS: &[&ChunkID] = &[
+    ids::COMMON,
+    ids::SOUND,
+    ids::MARKER,
+    ids::INSTRUMENT,
+    ids::COMMENTS,
+    ids::RECORDING,
+    ids::NAME,
+    ids::AUTHOR,
+    ids::COPYRIGHT,
+    ids::FVER,
+];
+
+// the chunks whose contents are checked
+const CHECKED_CHUNKS: &[&ChunkID] =
+    &[ids::COMMON, ids::MARKER, ids::INSTRUMENT, ids::COMMENTS];
+
+// checks the file from the start of the source. io errors fail validation
+// outright, everything else is reported
+pub fn validate<R: Read + Seek>(
+    r: &mut R,
+) -> Result<ValidationReport, ChunkError> {
+    let mut report = ValidationReport::default();
+    let file_len = r.seek(SeekFrom::End(0))?;
+    r.seek(SeekFrom::Start(0))?;
+
+    let mut header = vec![];
+    r.by_ref()
+        .take(detect::HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    let kind = match detect::detect_with_reason(&header) {
+        Ok(kind) => kind,
+        Err(e) => {
+            let message = format!("not an AIFF file, {:?}", e);
+            report.fail(Rule::FormType, None, message);
+            return Ok(report);
+        }
+    };
+    report.kind = Some(kind);
+
+    // taken as declared, an unsized FORM is reported rather than worked
+    // around
+    let (_, form_size) = walk::decode_header(&header);
+    let form_end = 8 + form_size as u64;
+    if form_end > file_len {
+        let message = format!(
+            "FORM size is {} but the file only has {} bytes after the header",
+            form_size,
+            file_len - 8
+        );
+        report.fail(Rule::FormSize, None, message);
+    }
+
+    let mut form = FormChunk::new();
+    let mut tag = None;
+    let mut sound_offset = None;
+    let options = ReaderOptions::new();
+
+    let end = form_end.min(file_len);
+    let mut chunks = ChunkWalk::new(end);
+    let mut pos = chunks.pos();
+    while let Some(start) = chunks.next_header() {
+        r.seek(SeekFrom::Start(start))?;
+        let (id, size) = match walk::read_header(r)? {
+            Some(header) => header,
+            None => break,
+        };
+        let header = ChunkHeader {
+            id,
+            offset: start,
+            size,
+        };
+
+        // past the end of the FORM, or of the file if that's shorter
+        if start + 8 + size as u64 > end {
+            let message = format!(
+                "chunk size is {} but only {} bytes are left",
+                size,
+                end - start - 8
+            );
+            report.fail(Rule::ChunkSize, Some(&header), message);
+            report.chunks.push(header);
+            pos = form_end;
+            break;
+        }
+
+        if CHECKED_CHUNKS.contains(&&id) {
+            let mut body = vec![];
+            r.by_ref().take(size as u64).read_to_end(&mut body)?;
+            let parsed = stream::add_chunk_body(
+                &mut form, &mut tag, &id, &body, &options,
+            );
+            if let Err(e) = parsed {
+                let message = format!("couldn't parse the chunk, {:?}", e);
+                report.fail(Rule::ChunkData, Some(&header), message);
+            }
+        } else if &id == ids::SOUND && size >= 8 {
+            let mut offset = [0; 4];
+            r.read_exact(&mut offset)?;
+            sound_offset = Some(u32::from_be_bytes(offset));
+        }
+
+        chunks.advance(id, size);
+        pos = chunks.pos();
+        // an odd sized chunk followed directly by the next one, rather than
+        // by its pad byte
+        if size % 2 == 1 && pos + 4 <= end {
+            let unpadded = peek_id(r, pos - 1)?;
+            let padded = peek_id(r, pos)?;
+            if is_plausible_id(&unpadded) && !is_plausible_id(&padded) {
+                let message = "odd sized chunk without a pad byte, the next \
+                               chunk starts at an odd offset"
+                    .to_owned();
+                report.fail(Rule::ChunkAlignment, Some(&header), message);
+                pos -= 1;
+                chunks.set_pos(pos);
+            }
+        }
+        report.chunks.push(header);
+    }
+
+    if pos != form_end && form_end <= file_len {
+        let message =
+            format!("FORM ends at {} but its chunks end at {}", form_end, pos);
+        report.fail(Rule::FormSize, None, message);
+    }
+
+    check_counts(&mut report, kind);
+    check_common(&mut report, &form, sound_offset);
+    check_markers(&mut report, &form);
+
+    Ok(report)
+}
+
+fn peek_id<R: Read + Seek>(r: &mut R, at: u64) -> Result<ChunkID, ChunkError> {
+    let mut id = [0; 4];
+    r.seek(SeekFrom::Start(at))?;
+    r.read_exact(&mut id)?;
+    Ok(id)
+}
+
+fn check_counts(report: &mut ValidationReport, kind: FormKind) {
+    let mut counts: HashMap<ChunkID, usize> = HashMap::new();
+    for h in &report.chunks {
+        *counts.entry(h.id).or_default() += 1;
+    }
+
+    for id in SINGLE_CHUNKS {
+        if let Some(n) = counts.get(*id).filter(|n| **n > 1) {
+            let header = report.chunks.iter().filter(|h| &h.id == *id).nth(1);
+            let message = format!("allowed once, found {} times", n);
+            let header = header.cloned();
+            report.fail(Rule::ChunkCount, header.as_ref(), message);
+        }
+    }
+
+    if !counts.contains_key(ids::COMMON) {
+        report.fail(Rule::RequiredChunk, None, "no COMM chunk".to_owned());
+    }
+    if kind == FormKind::AiffC && !counts.contains_key(ids::FVER) {
+        let message = "no FVER chunk, which AIFF-C requires".to_owned();
+        report.fail(Rule::RequiredChunk, None, message);
+    }
+}
+
+fn check_common(
+    report: &mut ValidationReport,
+    form: &FormChunk,
+    sound_offset: Option<u32>,
+) {
+    let common = match form.common() {
+        Some(c) => c,
+        None => return,
+    };
+    let comm = report.header(ids::COMMON).cloned();
+    let comm = comm.as_ref();
+
+    if !(1..=32).contains(&common.bit_rate) {
+        let message = format!("sample size is {}", common.bit_rate);
+        report.fail(Rule::SampleSize, comm, message);
+    }
+    if common.num_channels < 1 {
+        let message = format!("{} channels", common.num_channels);
+        report.fail(Rule::Channels, comm, message);
+    }
+    if !(common.sample_rate.is_finite() && common.sample_rate > 0.) {
+        let message = format!("sample rate is {}", common.sample_rate);
+        report.fail(Rule::SampleRate, comm, message);
+    }
+
+    let ssnd = report.header(ids::SOUND).cloned();
+    let ssnd = match ssnd {
+        Some(h) => h,
+        None if common.num_sample_frames > 0 => {
+            let message = format!(
+                "no SSND chunk, but COMM has {} sample frames",
+                common.num_sample_frames
+            );
+            report.fail(Rule::RequiredChunk, None, message);
+            return;
+        }
+        None => return,
+    };
+
+    // compressed AIFF-C data can't be measured from the frame count, AIFF-C
+    // holding plain samples can
+    let compressed = form.compression().is_some_and(|c| !stream::stores_pcm(c));
+    let frame_size = walk::frame_size(common) as u64;
+    let offset = sound_offset.unwrap_or(0) as u64;
+    let available = (ssnd.size as u64).saturating_sub(8 + offset);
+    let needed = common.num_sample_frames as u64 * frame_size;
+    if ssnd.size < 8 || offset + 8 > ssnd.size as u64 {
+        let message = format!(
+            "chunk size {} can't hold the offset and block size fields and \
+             an offset of {}",
+            ssnd.size, offset
+        );
+        report.fail(Rule::SoundDataSize, Some(&ssnd), message);
+    } else if !compressed && available < needed {
+        let message = format!(
+            "COMM has {} sample frames ({} bytes) but SSND holds {} bytes",
+            common.num_sample_frames, needed, available
+        );
+        report.fail(Rule::SoundDataSize, Some(&ssnd), message);
+    }
+}
+
+fn check_markers(report: &mut ValidationReport, form: &FormChunk) {
+    let markers = form.markers();
+    let mark = report.header(ids::MARKER).cloned();
+    let mark = mark.as_ref();
+
+    let mut seen = HashSet::new();
+    for m in markers.iter() {
+        if m.id <= 0 {
+            let message = format!("marker id {} isn't positive", m.id);
+            report.fail(Rule::MarkerId, mark, message);
+        } else if !seen.insert(m.id) {
+            let message = format!("marker id {} is used more than once", m.id);
+            report.fail(Rule::MarkerId, mark, message);
+        }
+
+        let frames = form.common().as_ref().map(|c| c.num_sample_frames);
+        if let Some(frames) = frames.filter(|f| m.position > *f) {
+            let message = format!(
+                "marker {} is at frame {}, past the last frame {}",
+                m.id, m.position, frames
+            );
+            report.fail(Rule::MarkerPosition, mark, message);
+        }
+    }
+
+    if let Some(inst) = form.instrument() {
+        let header = report.header(ids::INSTRUMENT).cloned();
+        let loops = [
+            ("sustain", &inst.sustain_loop),
+            ("release", &inst.release_loop),
+        ];
+        for (name, l) in &loops {
+            if let PlayMode::Unknown(mode) = l.play_mode {
+                let message = format!(
+                    "{} loop has unknown play mode {}, so it's ignored",
+                    name, mode
+                );
+                report.warn(Rule::LoopPlayMode, header.as_ref(), message);
+            }
+            if !l.play_mode.is_looping() {
+                continue;
+            }
+
+            let begin = markers.get(l.begin_loop);
+            let end = markers.get(l.end_loop);
+            for (id, marker) in &[(l.begin_loop, begin), (l.end_loop, end)] {
+                if marker.is_none() {
+                    let message =
+                        format!("{} loop uses missing marker {}", name, id);
+                    report.fail(Rule::LoopMarker, header.as_ref(), message);
+                }
+            }
+            // allowed, but the loop is ignored
+            if let (Some(b), Some(e)) = (begin, end) {
+                if b.position >= e.position {
+                    let message = format!(
+                        "{} loop begins at or after its end, so it's ignored",
+                        name
+                    );
+                    report.warn(Rule::LoopMarker, header.as_ref(), message);
+                }
+            }
+        }
+    }
+
+    if let Some(comments) = form.comments() {
+        let header = report.header(ids::COMMENTS).cloned();
+        for c in comments.comments() {
+            if let Some(id) =
+                c.linked_marker().filter(|id| markers.get(*id).is_none())
+            {
+                let message = format!("comment on missing marker {}", id);
+                report.fail(Rule::CommentMarker, header.as_ref(), message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    // AIFF-C with 2 channels of 16 bit, COMM declaring 4 frames and SSND
+    // holding 3
+    fn aifc(compression: &[u8; 4]) -> Vec<u8> {
+        let mut comm = vec![0, 2, 0, 0, 0, 4, 0, 16];
+        comm.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        comm.extend_from_slice(compression);
+        comm.extend_from_slice(b"\0\0");
+        let mut ssnd = vec![0; 8];
+        ssnd.extend(0..12);
+
+        let mut form = b"AIFC".to_vec();
+        form.extend(chunk(ids::FVER, &[0xA2, 0x80, 0x51, 0x40]));
+        form.extend(chunk(ids::COMMON, &comm));
+        form.extend(chunk(ids::SOUND, &ssnd));
+
+        let mut bytes = ids::FORM.to_vec();
+        bytes.extend_from_slice(&(form.len() as u32).to_be_bytes());
+        bytes.extend(form);
+        bytes
+    }
+
+    fn sound_data_size(compression: &[u8; 4]) -> Vec<Violation> {
+        let report = validate(&mut Cursor::new(aifc(compression))).unwrap();
+        assert_eq!(report.kind, Some(FormKind::AiffC));
+        report
+            .violations
+            .into_iter()
+            .filter(|v| v.rule == Rule::SoundDataSize)
+            .collect()
+    }
+
+    #[test]
+    fn short_sound_data() {
+        // plain samples, so the missing frame is noticed
+        for compression in &[b"NONE", b"sowt"] {
+            let violations = sound_data_size(compression);
+            assert_eq!(violations.len(), 1);
+            assert_eq!(violations[0].severity, Severity::Error);
+            assert_eq!(violations[0].chunk, Some(*ids::SOUND));
+        }
+        // compressed data can't be measured
+        assert!(sound_data_size(b"ima4").is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+// the chunk layout every reader walks: the 12 byte FORM header, then chunks
+// of an 8 byte id and size header, the body and a pad byte after odd sized
+// bodies. the readers get at the bytes differently (seeking, reading
+// straight through, slices, async), so this only decodes headers and does
+// the position arithmetic, the io is left to them
+use super::{
+    chunks::CommonChunk,
+    detect,
+    ids::{self, ChunkID},
+    probe::ChunkHeader,
+};
+use std::io::{self, Read};
+
+// large enough for the COMM fields of AIFF-C with a full length name
+pub(crate) const COMM_PROBE_LEN: u32 = 18 + 4 + 256;
+
+// the id and size from the first 8 bytes of `bytes`
+pub(crate) fn decode_header(bytes: &[u8]) -> (ChunkID, u32) {
+    let mut id = [0; 4];
+    id.copy_from_slice(&bytes[..4]);
+    let size = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    (id, size)
+}
+
+// reads into `buf` until it's full or the data ends, returning how much was
+// read. short reads are retried, so it works on pipes and sockets too
+fn fill(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// reads an id and size. None at the end of the data, including a header cut
+// off part way
+pub(crate) fn read_header(
+    r: &mut impl Read,
+) -> io::Result<Option<(ChunkID, u32)>> {
+    let mut header = [0; 8];
+    Ok(match fill(r, &mut header)? {
+        8 => Some(decode_header(&header)),
+        _ => None,
+    })
+}
+
+// as read_header, but a header cut off part way is an error. for readers
+// that go straight through, where that's the only sign of truncation
+pub(crate) fn read_whole_header(
+    r: &mut impl Read,
+) -> io::Result<Option<(ChunkID, u32)>> {
+    let mut header = [0; 8];
+    match fill(r, &mut header)? {
+        0 => Ok(None),
+        8 => Ok(Some(decode_header(&header))),
+        _ => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+// read_header over tokio's io traits
+#[cfg(feature = "tokio")]
+pub(crate) async fn read_header_async<R>(
+    r: &mut R,
+) -> io::Result<Option<(ChunkID, u32)>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut header = [0; 8];
+    let mut filled = 0;
+    while filled < header.len() {
+        match r.read(&mut header[filled..]).await {
+            Ok(0) => return Ok(None),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Some(decode_header(&header)))
+}
+
+// recorders that crash before finishing leave the FORM and SSND sizes at 0,
+// or at 0xFFFFFFFF when they write a placeholder
+pub(crate) fn is_unsized(size: u32) -> bool {
+    size == 0 || size == u32::MAX
+}
+
+// end of the FORM for a declared size, no further than `len`. an unsized
+// FORM runs to `len`, u64::MAX when the length isn't known
+pub(crate) fn form_end(form_size: u32, len: u64) -> u64 {
+    if is_unsized(form_size) {
+        len
+    } else {
+        len.min(8 + form_size as u64)
+    }
+}
+
+// bytes taken by one sample point, a partial byte rounds up
+pub(crate) fn point_size(bit_rate: i16) -> usize {
+    (bit_rate.max(0) as usize).div_ceil(8)
+}
+
+// bytes taken by one sample frame, 0 for a COMM with no channels
+pub(crate) fn frame_size(common: &CommonChunk) -> usize {
+    point_size(common.bit_rate) * common.num_channels.max(0) as usize
+}
+
+// the sample data in an SSND of `size` after its offset and block size
+// fields and `offset` bytes of padding. u64::MAX for an unsized SSND, which
+// runs to the end of the data
+pub(crate) fn sound_data_len(size: u32, offset: u32) -> u64 {
+    if is_unsized(size) {
+        u64::MAX
+    } else {
+        (size as u64).saturating_sub(8 + offset as u64)
+    }
+}
+
+// positions of the chunk headers in a FORM, without any io. the reader reads
+// the header at `next_header()`, then passes it to `advance` to move past
+// the chunk
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkWalk {
+    pos: u64,
+    end: u64,
+}
+
+impl ChunkWalk {
+    // chunks from just after the FORM header up to `end`
+    pub(crate) fn new(end: u64) -> ChunkWalk {
+        ChunkWalk {
+            pos: detect::HEADER_SIZE as u64,
+            end,
+        }
+    }
+
+    pub(crate) fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    // where the next chunk header is, None once there's no room for one
+    pub(crate) fn next_header(&self) -> Option<u64> {
+        if self.pos.saturating_add(8) <= self.end {
+            Some(self.pos)
+        } else {
+            None
+        }
+    }
+
+    // the header read at `next_header()`, moving past the chunk and its pad
+    // byte. unsized sound data runs to the end, so nothing comes after it
+    pub(crate) fn advance(&mut self, id: ChunkID, size: u32) -> ChunkHeader {
+        let header = ChunkHeader {
+            id,
+            offset: self.pos,
+            size,
+        };
+        self.pos = if &id == ids::SOUND && is_unsized(size) {
+            self.end
+        } else {
+            header.end()
+        };
+        header
+    }
+
+    // end of the body of a chunk from `advance`, cut short at the end of the
+    // walk
+    pub(crate) fn body_end(&self, header: &ChunkHeader) -> u64 {
+        if &header.id == ids::SOUND && is_unsized(header.size) {
+            return self.end;
+        }
+        self.end.min(header.offset + 8 + header.size as u64)
+    }
+
+    // for a chunk found somewhere other than where its predecessor said,
+    // e.g. after a missing pad byte
+    pub(crate) fn set_pos(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+}
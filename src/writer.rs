@@ -0,0 +1,261 @@
+use super::{
+    chunks::FormChunk,
+    ids::{self, ChunkID},
+    macroman::{self, TextDecoding},
+};
+use std::io::{self, Write};
+
+// the only FVER timestamp defined, May 23 1990 2:40pm
+const AIFC_VERSION_1: u32 = 0xA280_5140;
+
+// TODO stream SSND instead of buffering every chunk before writing
+pub struct AiffWriter<Sink: Write> {
+    sink: Sink,
+    id3_version: id3::Version,
+}
+
+impl<Sink: Write> AiffWriter<Sink> {
+    pub fn new(sink: Sink) -> AiffWriter<Sink> {
+        AiffWriter {
+            sink,
+            id3_version: id3::Version::Id3v24,
+        }
+    }
+
+    // the version ID3 tags are written as, 2.4 by default
+    pub fn id3_version(mut self, version: id3::Version) -> AiffWriter<Sink> {
+        self.id3_version = version;
+        self
+    }
+
+    // writes the FORM chunk and everything in it, as the form's type. the
+    // ID3 tag, if any, is stored in an "ID3 " chunk after the sound data
+    pub fn write_form(
+        &mut self,
+        form: &FormChunk,
+        id3v2_tag: Option<&id3::Tag>,
+    ) -> io::Result<()> {
+        let aifc = form.form_type() == ids::AIFF_C;
+        let mut chunks = vec![];
+        // AIFF-C requires a format version, first
+        let has_fver = form
+            .raw_chunks()
+            .iter()
+            .flatten()
+            .any(|c| &c.id == ids::FVER);
+        if aifc && !has_fver {
+            chunks.push((*ids::FVER, AIFC_VERSION_1.to_be_bytes().to_vec()));
+        }
+        for chunk in form.write_order() {
+            let mut data = vec![];
+            chunk.write_data(&mut data)?;
+            // AIFF-C adds the compression type and name to COMM
+            if aifc && &chunk.id() == ids::COMMON {
+                match form.compression() {
+                    Some(c) => {
+                        data.extend_from_slice(&c.compression_type);
+                        write_pstring(&mut data, &c.name)?;
+                    }
+                    None => {
+                        data.extend_from_slice(b"NONE");
+                        write_pstring(&mut data, "not compressed")?;
+                    }
+                }
+            }
+            chunks.push((chunk.id(), data));
+        }
+
+        if let Some(tag) = id3v2_tag {
+            let mut data = vec![];
+            tag.write_to(&mut data, self.id3_version)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            chunks.push((*ids::ID3_CHUNK, data));
+        }
+
+        // form type + every chunk header, body and pad byte
+        let form_size = chunks
+            .iter()
+            .fold(4, |size, (_, data)| size + 8 + padded_len(data.len()));
+        if form_size > i32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "form data exceeds the maximum chunk size",
+            ));
+        }
+
+        self.sink.write_all(ids::FORM)?;
+        self.sink.write_all(&(form_size as i32).to_be_bytes())?;
+        self.sink.write_all(form.form_type())?;
+        for (id, data) in &chunks {
+            write_chunk(&mut self.sink, id, data)?;
+        }
+
+        self.sink.flush()
+    }
+
+    pub fn into_inner(self) -> Sink {
+        self.sink
+    }
+}
+
+// id, size, data and the pad byte for odd sized chunks
+pub fn write_chunk(
+    w: &mut impl Write,
+    id: &ChunkID,
+    data: &[u8],
+) -> io::Result<()> {
+    w.write_all(id)?;
+    w.write_all(&(data.len() as i32).to_be_bytes())?;
+    w.write_all(data)?;
+    if data.len() % 2 == 1 {
+        w.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+fn padded_len(len: usize) -> usize {
+    len + len % 2
+}
+
+// text is always written as lossy MacRoman so saving never fails on
+// characters the format can't hold
+pub fn encode_text(text: &str) -> Vec<u8> {
+    macroman::encode(text, TextDecoding::Lossy).unwrap_or_default()
+}
+
+// a count byte followed by the text, padded so the total is even. text past
+// 255 bytes is truncated
+pub fn write_pstring(w: &mut dyn Write, s: &str) -> io::Result<()> {
+    let mut bytes = encode_text(s);
+    bytes.truncate(u8::MAX as usize);
+
+    w.write_all(&[bytes.len() as u8])?;
+    w.write_all(&bytes)?;
+    if (1 + bytes.len()) % 2 == 1 {
+        w.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+pub fn pstring_size(s: &str) -> usize {
+    padded_len(1 + s.chars().count().min(u8::MAX as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunks::TextChunkType, reader::AiffReader};
+    use std::io::Cursor;
+
+    fn pstring(s: &str) -> Vec<u8> {
+        let mut out = vec![];
+        write_pstring(&mut out, s).unwrap();
+        assert_eq!(out.len(), pstring_size(s));
+        out
+    }
+
+    #[test]
+    fn pstring_padding() {
+        assert_eq!(pstring(""), b"\0\0");
+        assert_eq!(pstring("ab"), b"\x02ab\0");
+        assert_eq!(pstring("abc"), b"\x03abc");
+        assert_eq!(pstring("Début"), b"\x05D\x8Ebut");
+    }
+
+    #[test]
+    fn pstring_truncates() {
+        let out = pstring(&"é".repeat(300));
+        assert_eq!(out.len(), 256);
+        assert_eq!(out[0], 255);
+        assert!(out[1..].iter().all(|b| *b == 0x8E));
+    }
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    // chunks in the order the writer puts them, so writing reproduces it
+    fn file() -> Vec<u8> {
+        let mut comm = vec![0, 2, 0, 0, 0, 4, 0, 16];
+        comm.extend_from_slice(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        let mut mark = vec![0, 1, 0, 1, 0, 0, 0, 2];
+        mark.extend_from_slice(b"\x05D\x8Ebut");
+        let mut ssnd = vec![0; 8];
+        ssnd.extend(0..16);
+
+        let mut tag = id3::Tag::new();
+        tag.set_title("Round Trip");
+        let mut id3 = vec![];
+        tag.write_to(&mut id3, id3::Version::Id3v23).unwrap();
+
+        let mut form = b"AIFF".to_vec();
+        form.extend(chunk(ids::COMMON, &comm));
+        form.extend(chunk(ids::MARKER, &mark));
+        form.extend(chunk(ids::NAME, b"abc"));
+        form.extend(chunk(b"XTRA", &[1, 2, 3]));
+        form.extend(chunk(ids::SOUND, &ssnd));
+        form.extend(chunk(ids::ID3_CHUNK, &id3));
+
+        let mut bytes = ids::FORM.to_vec();
+        bytes.extend_from_slice(&(form.len() as u32).to_be_bytes());
+        bytes.extend(form);
+        bytes
+    }
+
+    fn read(bytes: Vec<u8>) -> AiffReader<Cursor<Vec<u8>>> {
+        let mut reader = AiffReader::new(Cursor::new(bytes));
+        reader.read_all_form_data().unwrap();
+        reader
+    }
+
+    #[test]
+    fn read_write_read() {
+        let reader = read(file());
+        let mut written = vec![];
+        reader.write(&mut written).unwrap();
+        assert_eq!(written, file());
+
+        let reread = read(written);
+        let form = reread.form().as_ref().unwrap();
+        assert_eq!(form.common(), reader.form().as_ref().unwrap().common());
+        assert_eq!(form.text(TextChunkType::Name), Some("abc"));
+        let markers = form.markers();
+        assert_eq!(markers.get(1).unwrap().marker_name, "Début");
+        assert_eq!(form.raw_chunks().as_ref().unwrap()[0].id, *b"XTRA");
+        assert_eq!(reread.id3v2_version(), Some(id3::Version::Id3v23));
+        let tag = reread.id3v2_tag.as_ref().unwrap();
+        assert_eq!(tag.title(), Some("Round Trip"));
+        assert_eq!(reread.samples::<i16>(), reader.samples::<i16>());
+    }
+
+    #[test]
+    fn writes_aifc() {
+        let mut reader = read(file());
+        let form = reader.form_mut().as_mut().unwrap();
+        form.set_form_type(*ids::AIFF_C);
+
+        let mut written = vec![];
+        reader.write(&mut written).unwrap();
+        assert_eq!(&written[8..12], ids::AIFF_C);
+        // FVER first, then COMM with the compression fields
+        assert_eq!(&written[12..24], b"FVER\0\0\0\x04\xA2\x80\x51\x40");
+        assert_eq!(&written[24..32], b"COMM\0\0\0\x26");
+        assert_eq!(&written[50..55], b"NONE\x0e");
+
+        let reread = read(written);
+        let form = reread.form().as_ref().unwrap();
+        assert_eq!(form.form_type(), ids::AIFF_C);
+        let compression = form.compression().unwrap();
+        assert_eq!(compression.compression_type, *b"NONE");
+        assert_eq!(compression.name, "not compressed");
+        assert_eq!(reread.samples::<i16>(), reader.samples::<i16>());
+    }
+}